@@ -0,0 +1,385 @@
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::PathBuf};
+
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, Args, ValueEnum};
+use futures::{Stream, stream, executor::block_on};
+use serde::Serialize;
+
+use logparser_core::{parser, merge, session_key::SessionKeyConfig, session_analyzer::{Session, Sessionizer}, load_sessions, flush_sessions, LoadOptions, stats::{StatsOptions, GraphMode, calc_graph}, dimension::{Dimension, usage_stats}, funnel::{FunnelOptions, calc_funnel}, session_stats::session_summary, visitors::{VisitorKey, cohort_retention}, snapshot::{read_snapshot, write_snapshot}};
+
+/// Runs the log analysis outside of the browser and prints the result as JSON to stdout
+#[derive(Parser)]
+#[command(name = "log-sankey")]
+struct Cli {
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Transition graph between pages (the data for the Sankey diagram)
+	Graph {
+		#[command(flatten)]
+		input: InputArgs,
+		/// Number of layers (steps) in the graph
+		#[arg(long, default_value_t = 8)]
+		length: usize,
+		/// Minimal number of sessions for a path to get its own node
+		#[arg(long, default_value_t = 3)]
+		threshold: u32,
+		/// Maximal number of nodes in a layer
+		#[arg(long, default_value_t = 30)]
+		max_nodes: u32,
+		#[arg(long, default_value = "")]
+		must_contain: String,
+		/// Prefix of the first page of the sessions in the graph, with --backward of the last one
+		#[arg(long, default_value = "")]
+		must_start_with: String,
+		/// Layers go back in time from the last page matching --must-start-with, where the sessions end in the graph
+		#[arg(long)]
+		backward: bool,
+		/// Anchor the graph at the first page matching --must-contain, with this many layers before it
+		#[arg(long, conflicts_with = "backward")]
+		anchor_before: Option<usize>,
+		/// Only the matching sessions, e.g. "contains /h/** and not (ua *bot* or referer *google*)"
+		#[arg(long, default_value = "")]
+		filter: String,
+		/// JSON file with the path rewrite rules, replaces the default rules
+		#[arg(long)]
+		rewrite_rules: Option<PathBuf>,
+		/// Time zone of the dates in --filter, e.g. Europe/Prague
+		#[arg(long, default_value = "UTC", value_parser = parser::parse_timezone)]
+		timezone: Tz,
+	},
+	/// Distributions of the session duration, pages and bytes, and the sessions over time
+	Summary {
+		#[command(flatten)]
+		input: InputArgs,
+		/// Size of the time buckets in seconds
+		#[arg(long, default_value_t = 24 * 60 * 60)]
+		resolution: u32,
+		/// Time zone of the buckets, e.g. Europe/Prague
+		#[arg(long, default_value = "UTC", value_parser = parser::parse_timezone)]
+		timezone: Tz,
+	},
+	/// Weekly cohorts of the new visitors and how many of them came back in the next weeks
+	Cohorts {
+		#[command(flatten)]
+		input: InputArgs,
+		#[arg(long, value_enum, default_value_t = VisitorKeyArg::SessionIdentity)]
+		visitor_key: VisitorKeyArg,
+		/// Time zone of the weeks, e.g. Europe/Prague
+		#[arg(long, default_value = "UTC", value_parser = parser::parse_timezone)]
+		timezone: Tz,
+	},
+	/// Number of sessions which went through the steps in order
+	Funnel {
+		#[command(flatten)]
+		input: InputArgs,
+		/// Glob pattern of the path of a step, repeat for each step in order
+		#[arg(long = "step", required = true)]
+		steps: Vec<String>,
+		/// The steps must directly follow each other
+		#[arg(long)]
+		strict: bool,
+		/// Maximal number of seconds between two steps
+		#[arg(long)]
+		max_step_time: Option<u32>,
+	},
+	/// Usage statistics over time
+	Stats {
+		#[command(flatten)]
+		input: InputArgs,
+		#[arg(long, value_enum, default_value_t = StatsDimension::Path)]
+		by: StatsDimension,
+		/// Count only the first page of each session instead of all page views
+		#[arg(long)]
+		session_starts: bool,
+		/// Size of the time buckets in seconds
+		#[arg(long, default_value_t = 60 * 60)]
+		resolution: u32,
+		#[arg(long, default_value_t = 0)]
+		threshold: u32,
+		#[arg(long, default_value_t = 300)]
+		max_paths: u32,
+		/// Time zone of the buckets, e.g. Europe/Prague
+		#[arg(long, default_value = "UTC", value_parser = parser::parse_timezone)]
+		timezone: Tz,
+	},
+	/// Saves the parsed sessions to a binary snapshot, which opens in the web UI or with --from-snapshot
+	Snapshot {
+		#[command(flatten)]
+		input: InputArgs,
+		#[arg(long, short)]
+		output: PathBuf,
+		/// Keep the sessions active at the end of the logs open, the logs added with --from-snapshot later continue them
+		#[arg(long)]
+		keep_open_sessions: bool,
+	},
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StatsDimension {
+	Path,
+	Referer,
+	RefererDomain,
+	UserAgent,
+	UaFamily,
+	Domain,
+	StatusCode,
+	Method,
+	ContentType,
+	Country,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum VisitorKeyArg {
+	/// the identity the sessions were joined by (--session-key)
+	SessionIdentity,
+	/// IP address and user agent of the first request
+	IpUserAgent,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SessionKeyArg {
+	IpUserAgent,
+	/// session_id field of the log format (e.g. a cookie)
+	SessionId,
+	/// authenticated user
+	RemoteUser,
+	/// network prefix (--ipv4-prefix, --ipv6-prefix) and user agent
+	IpPrefix,
+}
+
+#[derive(Args)]
+struct InputArgs {
+	/// Log files to read (optionally gzip, bzip2 or zstd compressed), stdin is used when none (or `-`) is specified
+	files: Vec<PathBuf>,
+	/// Start with the sessions of a snapshot (see the snapshot command), the log files are optional and continue its open sessions (with the same --session-key and --referer-tree)
+	#[arg(long)]
+	from_snapshot: Option<PathBuf>,
+	/// Name of a built-in log format: ksp, common, combined (Apache/Nginx), caddy or traefik.
+	/// `auto` detects it from the first lines
+	#[arg(long, default_value = "auto")]
+	format: String,
+	/// JSON file with a custom log format (`LogFormat`), overrides --format
+	#[arg(long)]
+	format_file: Option<PathBuf>,
+	/// Don't strip the query string from paths and referers
+	#[arg(long)]
+	keep_query_string: bool,
+	/// Session is closed after this number of seconds of inactivity
+	#[arg(long, default_value_t = 60 * 60)]
+	max_age: u32,
+	/// Fail when more lines can not be parsed
+	#[arg(long)]
+	max_errors: Option<u64>,
+	/// Time zone of the logs which don't include the UTC offset (default UTC)
+	#[arg(long, value_parser = parser::parse_timezone)]
+	log_timezone: Option<Tz>,
+	/// Lines of a file which are out of order by less than this number of seconds are sorted
+	#[arg(long, default_value_t = merge::DEFAULT_REORDER_WINDOW)]
+	reorder_window: u32,
+	/// JSON file with the bot filter rules (`BotFilterConfig`), the known crawlers and the sessions with more than 40 pages are filtered by default
+	#[arg(long)]
+	bot_rules: Option<PathBuf>,
+	/// JSON file with the rules deciding which requests are page views and which are assets (`ClassifierConfig`)
+	#[arg(long)]
+	classifier_rules: Option<PathBuf>,
+	/// How the requests are joined into sessions
+	#[arg(long, value_enum, default_value_t = SessionKeyArg::IpUserAgent)]
+	session_key: SessionKeyArg,
+	#[arg(long, default_value_t = 24)]
+	ipv4_prefix: u8,
+	#[arg(long, default_value_t = 64)]
+	ipv6_prefix: u8,
+	/// Attach each page to the page in its referer, the graph is built from the branches of the navigation tree
+	#[arg(long)]
+	referer_tree: bool,
+	/// Pretty-print the output JSON
+	#[arg(long)]
+	pretty: bool,
+}
+
+/// The stream ends after a read error
+fn read_chunks(mut reader: Box<dyn Read>) -> impl Stream<Item=Result<Vec<u8>, String>> {
+	let mut failed = false;
+	stream::iter(std::iter::from_fn(move || {
+		if failed {
+			return None;
+		}
+		let mut buffer = vec![0u8; 1 << 16];
+		loop {
+			match reader.read(&mut buffer) {
+				Ok(0) => return None,
+				Ok(len) => {
+					buffer.truncate(len);
+					return Some(Ok(buffer))
+				},
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+				Err(e) => {
+					failed = true;
+					return Some(Err(format!("Could not read the input: {}", e)))
+				}
+			}
+		}
+	}))
+}
+
+fn open_inputs(files: &[PathBuf]) -> io::Result<Vec<Box<dyn Read>>> {
+	if files.is_empty() {
+		return Ok(vec![ Box::new(io::stdin()) ])
+	}
+	files.iter().map(|f| -> io::Result<Box<dyn Read>> {
+		if f.as_os_str() == "-" {
+			Ok(Box::new(io::stdin()))
+		} else {
+			Ok(Box::new(File::open(f)?))
+		}
+	}).collect()
+}
+
+fn read_format(input: &InputArgs) -> io::Result<parser::FormatSpec> {
+	match &input.format_file {
+		Some(f) => {
+			let format = serde_json::from_reader(File::open(f)?)?;
+			Ok(parser::FormatSpec::Custom(format))
+		},
+		None => Ok(parser::FormatSpec::Preset(input.format.clone())),
+	}
+}
+
+fn load(input: &InputArgs, table: &mut parser::GlobalTable) -> io::Result<Vec<Session>> {
+	load_into(input, table, &mut Sessionizer::default(), false)
+}
+
+/// With `keep_open_sessions`, the sessions active at the end of the logs are left in the sessionizer instead of the result
+fn load_into(input: &InputArgs, table: &mut parser::GlobalTable, sessionizer: &mut Sessionizer, keep_open_sessions: bool) -> io::Result<Vec<Session>> {
+	let options = LoadOptions {
+		format: read_format(input)?,
+		ignore_query_string: !input.keep_query_string,
+		max_age: input.max_age,
+		max_errors: input.max_errors,
+		timezone: input.log_timezone,
+		reorder_window: input.reorder_window,
+		bot_filter: match &input.bot_rules {
+			Some(f) => serde_json::from_reader(File::open(f)?)?,
+			None => Default::default(),
+		},
+		session_key: match input.session_key {
+			SessionKeyArg::IpUserAgent => SessionKeyConfig::IpUserAgent,
+			SessionKeyArg::SessionId => SessionKeyConfig::SessionId,
+			SessionKeyArg::RemoteUser => SessionKeyConfig::RemoteUser,
+			SessionKeyArg::IpPrefix => SessionKeyConfig::IpPrefix { ipv4_prefix: input.ipv4_prefix, ipv6_prefix: input.ipv6_prefix },
+		},
+		referer_tree: input.referer_tree,
+		classifier: match &input.classifier_rules {
+			Some(f) => serde_json::from_reader(File::open(f)?)?,
+			None => Default::default(),
+		},
+		keep_open_sessions,
+	};
+	let mut sessions = vec![];
+	if let Some(f) = &input.from_snapshot {
+		(*table, sessions, *sessionizer) = read_snapshot(BufReader::new(File::open(f)?))
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		if input.files.is_empty() {
+			if !keep_open_sessions {
+				sessions.extend(flush_sessions(sessionizer, &options, table).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?);
+			}
+			return Ok(sessions);
+		}
+	}
+	let streams = open_inputs(&input.files)?.into_iter().map(read_chunks).collect();
+	let mut result = block_on(load_sessions(streams, &options, table, sessionizer))
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+	sessions.append(&mut result.sessions);
+	Ok(sessions)
+}
+
+fn print_json<T: Serialize>(value: &T, pretty: bool) -> io::Result<()> {
+	let mut out = BufWriter::new(io::stdout().lock());
+	if pretty { serde_json::to_writer_pretty(&mut out, value) } else { serde_json::to_writer(&mut out, value) }?;
+	writeln!(out)?;
+	out.flush()
+}
+
+fn run(cli: Cli) -> io::Result<()> {
+	let mut table = parser::GlobalTable::new();
+
+	match cli.command {
+		Command::Graph { input, length, threshold, max_nodes, must_contain, must_start_with, backward, anchor_before, filter, rewrite_rules, timezone } => {
+			let sessions = load(&input, &mut table)?;
+			let mut opt = StatsOptions { filter, timezone, ..StatsOptions::new(0, threshold, max_nodes) };
+			if let Some(f) = rewrite_rules {
+				opt.rewrite_rules = serde_json::from_reader(File::open(f)?)?;
+			}
+			let mode = match anchor_before {
+				Some(before) => GraphMode::Anchored { before },
+				None if backward => GraphMode::Backward,
+				None => GraphMode::Forward,
+			};
+			let g = calc_graph(&sessions, &table, length, &opt, &must_contain, &must_start_with, mode)
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+			print_json(&g, input.pretty)?;
+		},
+		Command::Summary { input, resolution, timezone } => {
+			let sessions = load(&input, &mut table)?;
+			let opt = StatsOptions { timezone, ..StatsOptions::new(resolution, 0, 0) };
+			print_json(&session_summary(&sessions, &opt), input.pretty)?;
+		},
+		Command::Cohorts { input, visitor_key, timezone } => {
+			let sessions = load(&input, &mut table)?;
+			let key = match visitor_key {
+				VisitorKeyArg::SessionIdentity => VisitorKey::SessionIdentity,
+				VisitorKeyArg::IpUserAgent => VisitorKey::IpUserAgent,
+			};
+			print_json(&cohort_retention(&sessions, key, &timezone), input.pretty)?;
+		},
+		Command::Funnel { input, steps, strict, max_step_time } => {
+			let sessions = load(&input, &mut table)?;
+			let f = calc_funnel(&sessions, &table, &FunnelOptions { steps, strict, max_step_time })
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+			print_json(&f, input.pretty)?;
+		},
+		Command::Stats { input, by, session_starts, resolution, threshold, max_paths, timezone } => {
+			let sessions = load(&input, &mut table)?;
+			let opt = StatsOptions { timezone, ..StatsOptions::new(resolution, threshold, max_paths) };
+			let dimension = match by {
+				StatsDimension::Path => Dimension::Path,
+				StatsDimension::Referer => Dimension::Referer,
+				StatsDimension::RefererDomain => Dimension::RefererDomain,
+				StatsDimension::UserAgent => Dimension::UserAgent,
+				StatsDimension::UaFamily => Dimension::UaFamily,
+				StatsDimension::Domain => Dimension::Domain,
+				StatsDimension::StatusCode => Dimension::StatusCode,
+				StatsDimension::Method => Dimension::Method,
+				StatsDimension::ContentType => Dimension::ContentType,
+				StatsDimension::Country => Dimension::Country,
+			};
+			let r = usage_stats(&sessions, &table, dimension, &opt, !session_starts);
+			print_json(&r, input.pretty)?;
+		},
+		Command::Snapshot { input, output, keep_open_sessions } => {
+			let mut sessionizer = Sessionizer::default();
+			let sessions = load_into(&input, &mut table, &mut sessionizer, keep_open_sessions)?;
+			write_snapshot(BufWriter::new(File::create(output)?), &table, &sessions, &sessionizer)
+				.map_err(io::Error::other)?;
+		},
+	}
+	Ok(())
+}
+
+fn main() {
+	env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+	let cli = Cli::parse();
+	match run(cli) {
+		// the output was closed, e.g. by `| head`
+		Err(e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+		Err(e) => {
+			eprintln!("Error: {}", e);
+			std::process::exit(1);
+		},
+		Ok(()) => (),
+	}
+}
@@ -0,0 +1,256 @@
+//! Removal of the sessions made by bots. The rules are checked in order and the first matching one excludes the session.
+use std::{collections::HashMap, net::IpAddr};
+
+use ipnet::IpNet;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+use crate::{parser::GlobalTable, session_analyzer::Session, stats::make_inverse_core};
+
+/// Lowercase user agent substrings of the known crawlers, see `bot_user_agents.txt`
+const KNOWN_USER_AGENTS: &str = include_str!("bot_user_agents.txt");
+/// Words which are matched at the end of a product name, see `ends_word`
+const BOT_WORDS: [&str; 3] = ["bot", "crawler", "spider"];
+/// Devices with a bot word in the name
+const NOT_BOTS: [&str; 1] = ["cubot"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum BotRule {
+	/// the built-in list of crawlers, HTTP libraries and monitoring tools, and user agents with the bot, crawler or spider word
+	KnownCrawlers,
+	/// user agent contains the string (case sensitive)
+	Substring { pattern: String },
+	/// user agent matches the regular expression
+	Regex { pattern: String },
+	/// client IP is in the range, e.g. `66.249.64.0/19`
+	IpRange { range: String },
+	/// sessions with at least `min_pages` pages which did not load any styles, scripts or images.
+	/// Not in the defaults, the browsers of the returning visitors have the assets cached (or get `304 Not Modified`, which is ignored)
+	NoAssets { min_pages: u32 },
+	/// sessions with more than `max_pages` pages
+	TooManyPages { max_pages: u32 },
+}
+
+impl BotRule {
+	pub fn describe(&self) -> String {
+		match self {
+			BotRule::KnownCrawlers => "known crawlers".to_owned(),
+			BotRule::Substring { pattern } => format!("user agent contains {}", pattern),
+			BotRule::Regex { pattern } => format!("user agent matches {}", pattern),
+			BotRule::IpRange { range } => format!("IP in {}", range),
+			BotRule::NoAssets { min_pages } => format!("{} or more pages without assets", min_pages),
+			BotRule::TooManyPages { max_pages } => format!("more than {} pages", max_pages),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotFilterConfig {
+	pub rules: Vec<BotRule>,
+}
+
+impl Default for BotFilterConfig {
+	fn default() -> Self {
+		BotFilterConfig { rules: vec![
+			BotRule::KnownCrawlers,
+			BotRule::TooManyPages { max_pages: 40 },
+		] }
+	}
+}
+
+/// Number of the sessions excluded by a rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotRuleStats {
+	pub rule: String,
+	pub sessions: u64,
+	pub requests: u64,
+}
+
+enum CompiledRule {
+	KnownCrawlers(Vec<&'static str>),
+	Substring(String),
+	Regex(Regex),
+	IpRange(IpNet),
+	NoAssets(u32),
+	TooManyPages(u32),
+}
+
+pub struct BotFilter {
+	rules: Vec<CompiledRule>,
+	descriptions: Vec<String>,
+}
+
+impl BotFilter {
+	pub fn new(config: &BotFilterConfig) -> Result<BotFilter, String> {
+		let rules = config.rules.iter().map(|rule| Ok(match rule {
+			BotRule::KnownCrawlers => CompiledRule::KnownCrawlers(known_user_agents().collect()),
+			BotRule::Substring { pattern } => CompiledRule::Substring(pattern.clone()),
+			BotRule::Regex { pattern } => CompiledRule::Regex(Regex::new(pattern).map_err(|e| e.to_string())?),
+			BotRule::IpRange { range } => CompiledRule::IpRange(
+				range.parse().or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+					.map_err(|_| format!("Invalid IP range {}, expected e.g. 66.249.64.0/19", range))?),
+			&BotRule::NoAssets { min_pages } => CompiledRule::NoAssets(min_pages),
+			&BotRule::TooManyPages { max_pages } => CompiledRule::TooManyPages(max_pages),
+		})).collect::<Result<Vec<_>, String>>()?;
+		Ok(BotFilter { rules, descriptions: config.rules.iter().map(BotRule::describe).collect() })
+	}
+
+	fn match_user_agent(&self, ua: &str) -> Option<usize> {
+		let lowercase = ua.to_lowercase();
+		self.rules.iter().position(|r| match r {
+			CompiledRule::KnownCrawlers(list) => list.iter().any(|x| lowercase.contains(x)) || has_bot_word(&lowercase),
+			CompiledRule::Substring(s) => ua.contains(s.as_str()),
+			CompiledRule::Regex(r) => r.is_match(ua),
+			_ => false,
+		})
+	}
+
+	fn match_ip(&self, ip: &str) -> Option<usize> {
+		let ip: IpAddr = ip.parse().ok()?;
+		self.rules.iter().position(|r| matches!(r, CompiledRule::IpRange(range) if range.contains(&ip)))
+	}
+
+	fn match_behavior(&self, s: &Session) -> Option<usize> {
+		self.rules.iter().position(|r| match *r {
+			CompiledRule::NoAssets(min_pages) => s.actions.len() >= min_pages as usize && s.total_requests as usize <= s.actions.len(),
+			CompiledRule::TooManyPages(max_pages) => s.actions.len() > max_pages as usize,
+			_ => false,
+		})
+	}
+
+	/// Removes the bot sessions, returns the number of sessions excluded by each rule
+	pub fn filter(&self, sessions: &mut Vec<Session>, table: &GlobalTable) -> Vec<BotRuleStats> {
+		let mut stats: Vec<BotRuleStats> = self.descriptions.iter().map(|d| BotRuleStats { rule: d.clone(), sessions: 0, requests: 0 }).collect();
+		if table.user_agent.is_empty() || table.ip.is_empty() {
+			return stats;
+		}
+		let user_agents = make_inverse_core(&table.user_agent, "");
+		let ips = make_inverse_core(&table.ip, "");
+		// sessions share the user agents and IPs, the string rules are checked once for each
+		let mut ua_cache: HashMap<u32, Option<usize>> = HashMap::new();
+		let mut ip_cache: HashMap<u32, Option<usize>> = HashMap::new();
+
+		sessions.retain(|s| {
+			let ua = *ua_cache.entry(s.user_agent).or_insert_with(|| self.match_user_agent(user_agents[s.user_agent as usize]));
+			let ip = *ip_cache.entry(s.ip).or_insert_with(|| self.match_ip(ips[s.ip as usize]));
+			let rule = [ua, ip, self.match_behavior(s)].into_iter().flatten().min();
+			if let Some(rule) = rule {
+				stats[rule].sessions += 1;
+				stats[rule].requests += s.total_requests as u64;
+			}
+			rule.is_none()
+		});
+		stats
+	}
+}
+
+fn known_user_agents() -> impl Iterator<Item=&'static str> {
+	KNOWN_USER_AGENTS.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'))
+}
+
+/// The `BotRule::KnownCrawlers` check of a lowercase user agent
+pub(crate) fn is_known_crawler(lowercase_ua: &str) -> bool {
+	known_user_agents().any(|x| lowercase_ua.contains(x)) || has_bot_word(lowercase_ua)
+}
+
+fn has_bot_word(lowercase_ua: &str) -> bool {
+	BOT_WORDS.iter().any(|w| ends_word(lowercase_ua, w))
+}
+
+/// `word` ends a word (`Googlebot/2.1`, `compatible; bingbot)`, `SemrushBot` at the end), but not in `Bottle` or a device in `NOT_BOTS` (`CUBOT_P30`)
+fn ends_word(s: &str, word: &str) -> bool {
+	s.match_indices(word).any(|(i, _)| {
+		let end = i + word.len();
+		!s[end..].starts_with(char::is_alphanumeric) && !NOT_BOTS.iter().any(|d| s[..end].ends_with(d))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::DateTime;
+
+	use super::*;
+	use crate::{parser::get_or_add, session_key::Identity};
+
+	/// Session of `pages` page views and `requests` requests in total
+	fn session(table: &mut GlobalTable, user_agent: &str, pages: u32, requests: u32) -> Session {
+		let ip = get_or_add(&mut table.ip, "1.1.1.1");
+		let user_agent = get_or_add(&mut table.user_agent, user_agent);
+		let time = DateTime::from_timestamp(0, 0).unwrap();
+		Session {
+			identity: Identity::IpUserAgent { ip, user_agent },
+			ip, user_agent, referer: 0, country: 0,
+			start_time: time, end_time: time, last_request_time: time,
+			actions: (0..pages).map(|p| table.add_path(&format!("/{}", p))).collect(),
+			access_times: vec![0; pages as usize],
+			details: vec![],
+			parents: vec![],
+			total_requests: requests,
+			total_bytes: 0,
+		}
+	}
+
+	#[test]
+	fn keeps_the_sessions_with_cached_assets_by_default() {
+		let mut table = GlobalTable::new();
+		let browser = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+		// a returning visitor has the assets cached, the requests are only the pages
+		let returning = session(&mut table, browser, 3, 3);
+		let new = session(&mut table, browser, 3, 12);
+		let mut sessions = vec![returning.clone(), new.clone()];
+		let stats = BotFilter::new(&BotFilterConfig::default()).unwrap().filter(&mut sessions, &table);
+		assert_eq!(sessions.len(), 2);
+		assert!(stats.iter().all(|s| s.sessions == 0));
+
+		let config = BotFilterConfig { rules: vec![BotRule::NoAssets { min_pages: 2 }] };
+		let mut sessions = vec![returning, new];
+		let stats = BotFilter::new(&config).unwrap().filter(&mut sessions, &table);
+		assert_eq!(sessions.len(), 1);
+		assert_eq!((stats[0].sessions, stats[0].requests), (1, 3));
+	}
+
+	#[test]
+	fn finds_the_bot_words() {
+		for ua in [
+			"Mozilla/5.0 (compatible; SeekportBot; +https://bot.seekport.com)",
+			"Mozilla/5.0 (compatible; Barkrowler/0.9; +https://babbar.tech/crawler)",
+			"Mozilla/5.0 (compatible; Bytespider; spider-feedback@bytedance.com)",
+			"Mozilla/5.0 (compatible; MojeekBot/0.11; +https://www.mojeek.com/bot.html)",
+			"Mozilla/5.0 (compatible; ExampleBot)",
+			"ExampleBot (+https://example.com/bot)",
+			"Sogou web spider/4.0(+http://www.sogou.com/docs/help/webmasters.htm#07)",
+		] {
+			assert!(has_bot_word(&ua.to_lowercase()), "{}", ua);
+		}
+		for ua in [
+			"Mozilla/5.0 (Linux; Android 10; CUBOT_P30) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Mobile Safari/537.36",
+			"Mozilla/5.0 (Linux; Android 11; Cubot X30) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Mobile Safari/537.36",
+			"Bottle/0.12 python",
+			"Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:120.0) Gecko/20100101 Firefox/120.0",
+		] {
+			assert!(!has_bot_word(&ua.to_lowercase()), "{}", ua);
+		}
+	}
+
+	#[test]
+	fn knows_the_crawlers() {
+		for ua in [
+			"Mozilla/5.0 AppleWebKit/537.36 (KHTML, like Gecko; compatible; Googlebot/2.1; +http://www.google.com/bot.html) Chrome/120.0.6099.216 Safari/537.36",
+			"Mozilla/5.0 (compatible; bingbot/2.0; +http://www.bing.com/bingbot.htm)",
+			"Mozilla/5.0 (compatible; YandexBot/3.0; +http://yandex.com/bots)",
+			"Mozilla/5.0 (compatible; AhrefsBot/7.0; +http://ahrefs.com/robot/)",
+			"facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)",
+			"curl/8.4.0",
+		] {
+			assert!(is_known_crawler(&ua.to_lowercase()), "{}", ua);
+		}
+		// the Yandex and NAVER apps are browsers
+		for ua in [
+			"Mozilla/5.0 (Linux; Android 13) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 YaBrowser/23.11 YandexSearch/23.112 Mobile Safari/537.36",
+			"Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148 NAVER(inapp; search; 2000; 12.1.2)",
+		] {
+			assert!(!is_known_crawler(&ua.to_lowercase()), "{}", ua);
+		}
+	}
+}
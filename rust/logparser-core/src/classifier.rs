@@ -0,0 +1,229 @@
+//! Decides which requests are page views (the actions of the sessions) and which only load the assets of a page
+use std::collections::HashMap;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Serialize, Deserialize};
+
+use crate::{parser::{GlobalTable, LogLine}, stats::make_inverse_core};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestClass {
+	/// page view, an action of the session
+	#[default]
+	Page,
+	/// loaded by a page (styles, scripts, fonts), never starts a session
+	Asset,
+	/// an asset when it's loaded shortly after a page, a page view when it's opened on its own (e.g. an image)
+	ProbablyAsset,
+	/// not counted at all
+	Ignored,
+}
+
+/// The rule applies when all the specified conditions match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClassRule {
+	/// file extensions without the dot (`css`)
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub extensions: Vec<String>,
+	/// `text/css`, or `image/*` for all the subtypes
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub content_types: Vec<String>,
+	/// glob patterns (`/api/**`)
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub paths: Vec<String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub methods: Vec<String>,
+	/// status codes (`404`) or classes (`4xx`)
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub statuses: Vec<String>,
+	pub class: RequestClass,
+}
+
+/// The first matching rule decides, `default` is used when none matches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassifierConfig {
+	pub rules: Vec<ClassRule>,
+	#[serde(default)]
+	pub default: RequestClass,
+}
+
+impl Default for ClassifierConfig {
+	fn default() -> Self {
+		let strings = |x: &[&str]| x.iter().map(|&s| s.to_owned()).collect::<Vec<_>>();
+		ClassifierConfig {
+			rules: vec![
+				// redirects, not modified and errors
+				ClassRule { statuses: strings(&["1xx", "3xx", "4xx", "5xx"]), class: RequestClass::Ignored, ..Default::default() },
+				ClassRule { content_types: strings(&["text/css", "text/javascript", "application/javascript"]), class: RequestClass::Asset, ..Default::default() },
+				ClassRule { extensions: strings(&["js", "css", "ico", "svg", "woff", "woff2", "ttf", "eot", "otf", "feed"]), class: RequestClass::Asset, ..Default::default() },
+				ClassRule { content_types: strings(&["text/json", "application/json", "image/png", "image/jpeg", "image/gif", "image/svg+xml"]), class: RequestClass::ProbablyAsset, ..Default::default() },
+				ClassRule { extensions: strings(&["png", "jpg", "gif"]), class: RequestClass::ProbablyAsset, ..Default::default() },
+			],
+			default: RequestClass::Page,
+		}
+	}
+}
+
+struct CompiledRule {
+	extensions: Vec<String>,
+	content_types: Vec<String>,
+	paths: Option<GlobSet>,
+	methods: Vec<String>,
+	/// inclusive ranges
+	statuses: Vec<(u32, u32)>,
+	class: RequestClass,
+}
+
+pub(crate) fn parse_status(s: &str) -> Result<(u32, u32), String> {
+	let err = || format!("Invalid status {}, expected e.g. 404 or 4xx", s);
+	match s.strip_suffix("xx") {
+		Some(class) => {
+			let class: u32 = class.parse().map_err(|_| err())?;
+			Ok((class * 100, class * 100 + 99))
+		},
+		None => s.parse().map(|x| (x, x)).map_err(|_| err()),
+	}
+}
+
+pub struct Classifier {
+	rules: Vec<CompiledRule>,
+	default: RequestClass,
+	/// the lookups are done for every request, but the combinations repeat a lot
+	cache: HashMap<(u32, u32, u32, u32), RequestClass>,
+	content_types: Vec<String>,
+	methods: Vec<String>,
+}
+
+impl Classifier {
+	pub fn new(config: &ClassifierConfig) -> Result<Classifier, String> {
+		let rules = config.rules.iter().map(|r| {
+			let paths = if r.paths.is_empty() { None } else {
+				let mut b = GlobSetBuilder::new();
+				for p in &r.paths {
+					b.add(Glob::new(p).map_err(|e| e.to_string())?);
+				}
+				Some(b.build().map_err(|e| e.to_string())?)
+			};
+			Ok(CompiledRule {
+				extensions: r.extensions.iter().map(|e| format!(".{}", e.trim_start_matches('.').to_lowercase())).collect(),
+				content_types: r.content_types.clone(),
+				paths,
+				methods: r.methods.iter().map(|m| m.to_uppercase()).collect(),
+				statuses: r.statuses.iter().map(|s| parse_status(s)).collect::<Result<_, String>>()?,
+				class: r.class,
+			})
+		}).collect::<Result<Vec<_>, String>>()?;
+		Ok(Classifier { rules, default: config.default, cache: HashMap::new(), content_types: vec![], methods: vec![] })
+	}
+
+	pub fn classify(&mut self, l: &LogLine, table: &GlobalTable) -> RequestClass {
+		let key = (l.path, l.content_type, l.method, l.status_code);
+		if let Some(&c) = self.cache.get(&key) {
+			return c;
+		}
+		if l.content_type as usize >= self.content_types.len() || l.method as usize >= self.methods.len() {
+			// the table grows while the logs are read, but new content types and methods are rare
+			self.content_types = make_inverse_core(&table.content_type, "").into_iter().map(str::to_owned).collect();
+			self.methods = make_inverse_core(&table.method, "").into_iter().map(str::to_owned).collect();
+		}
+
+		let path = table.path_list[l.path as usize].as_str();
+		let lowercase_path = path.to_lowercase();
+		let content_type = self.content_types[l.content_type as usize].as_str();
+		let method = self.methods[l.method as usize].as_str();
+		let class = self.rules.iter().find(|r|
+			(r.extensions.is_empty() || r.extensions.iter().any(|e| lowercase_path.ends_with(e.as_str()))) &&
+			(r.content_types.is_empty() || r.content_types.iter().any(|t| content_type_matches(t, content_type))) &&
+			r.paths.as_ref().is_none_or(|p| p.is_match(path)) &&
+			(r.methods.is_empty() || r.methods.iter().any(|m| m == method)) &&
+			(r.statuses.is_empty() || r.statuses.iter().any(|&(from, to)| l.status_code >= from && l.status_code <= to))
+		).map_or(self.default, |r| r.class);
+
+		self.cache.insert(key, class);
+		class
+	}
+}
+
+fn content_type_matches(pattern: &str, content_type: &str) -> bool {
+	match pattern.strip_suffix("/*") {
+		Some(prefix) => content_type.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/')),
+		None => pattern == content_type,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parser::{FormatSpec, LogParser};
+
+	/// Classifies KSP log lines, the table is shared because the classifier caches the ids
+	struct Requests {
+		classifier: Classifier,
+		table: GlobalTable,
+		parser: LogParser,
+	}
+
+	impl Requests {
+		fn new(config: &ClassifierConfig) -> Requests {
+			let parser = LogParser::new(&FormatSpec::Preset("ksp".to_owned()), true).unwrap();
+			Requests { classifier: Classifier::new(config).unwrap(), table: GlobalTable::new(), parser }
+		}
+
+		/// Class of the request with the response content type (`-` for none)
+		fn classify(&mut self, method: &str, path: &str, status: u32, content_type: &str) -> RequestClass {
+			let line = format!(r#"2021-05-01 02:16:15 "1.1.1.1" "HTTP/1.1" {} example.com "{}" {} 100 0 "-" "Mozilla/5.0" "-" 1 "{}" "-""#, method, path, status, content_type);
+			let line = self.parser.parse_line(&mut self.table, &line).unwrap();
+			self.classifier.classify(&line, &self.table)
+		}
+	}
+
+	#[test]
+	fn default_rules_match_the_old_checks() {
+		let mut c = Requests::new(&ClassifierConfig::default());
+		for (path, status, content_type, class) in [
+			("/a", 200, "text/html", RequestClass::Page),
+			("/a", 301, "text/html", RequestClass::Ignored),
+			("/style.css", 304, "-", RequestClass::Ignored),
+			("/missing", 404, "text/html", RequestClass::Ignored),
+			("/a", 503, "text/html", RequestClass::Ignored),
+			("/style.css", 200, "-", RequestClass::Asset),
+			("/app.JS", 200, "-", RequestClass::Asset),
+			("/bundle", 200, "application/javascript", RequestClass::Asset),
+			("/fonts/a.woff2", 200, "-", RequestClass::Asset),
+			("/api/data", 200, "application/json", RequestClass::ProbablyAsset),
+			("/img/logo", 200, "image/png", RequestClass::ProbablyAsset),
+			("/photo.jpg", 200, "-", RequestClass::ProbablyAsset),
+		] {
+			assert_eq!(c.classify("GET", path, status, content_type), class, "{} {}", path, status);
+		}
+	}
+
+	#[test]
+	fn matches_the_globs_statuses_and_methods() {
+		let config = ClassifierConfig {
+			rules: vec![
+				ClassRule { paths: vec!["/api/**".to_owned()], methods: vec!["post".to_owned()], class: RequestClass::Ignored, ..Default::default() },
+				ClassRule { statuses: vec!["4xx".to_owned(), "500".to_owned()], class: RequestClass::Ignored, ..Default::default() },
+				ClassRule { content_types: vec!["image/*".to_owned()], class: RequestClass::Asset, ..Default::default() },
+			],
+			default: RequestClass::Page,
+		};
+		let mut c = Requests::new(&config);
+		assert_eq!(c.classify("POST", "/api/v1/users", 200, "-"), RequestClass::Ignored);
+		assert_eq!(c.classify("GET", "/api/v1/users", 200, "-"), RequestClass::Page);
+		assert_eq!(c.classify("POST", "/apis", 200, "-"), RequestClass::Page);
+		assert_eq!(c.classify("GET", "/a", 400, "-"), RequestClass::Ignored);
+		assert_eq!(c.classify("GET", "/a", 499, "-"), RequestClass::Ignored);
+		assert_eq!(c.classify("GET", "/a", 500, "-"), RequestClass::Ignored);
+		assert_eq!(c.classify("GET", "/a", 502, "-"), RequestClass::Page);
+		assert_eq!(c.classify("GET", "/a", 399, "-"), RequestClass::Page);
+		assert_eq!(c.classify("GET", "/a", 200, "image/webp"), RequestClass::Asset);
+		assert_eq!(c.classify("GET", "/a", 200, "imagery/x"), RequestClass::Page);
+
+		assert_eq!(parse_status("2xx"), Ok((200, 299)));
+		assert!(parse_status("4x").is_err());
+		let invalid_glob = ClassifierConfig { rules: vec![ClassRule { paths: vec!["/a/[".to_owned()], ..Default::default() }], default: RequestClass::Page };
+		assert!(Classifier::new(&invalid_glob).is_err());
+	}
+}
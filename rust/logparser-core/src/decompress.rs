@@ -0,0 +1,293 @@
+//! Streaming decompression of the input files. The compression is detected from the magic bytes,
+//! so `access.log.2.gz` can be loaded the same way as the plain `access.log`.
+use std::{io::{self, Write}, mem};
+
+use futures::{Stream, StreamExt, stream, future};
+use ruzstd::decoding::{FrameDecoder, errors::{FrameDecoderError, ReadFrameHeaderError}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+	None,
+	Gzip,
+	Bzip2,
+	Zstd,
+}
+
+/// Number of bytes needed by `Compression::detect`
+const MAGIC_LENGTH: usize = 4;
+
+impl Compression {
+	/// Detects the compression from the first bytes of the file
+	pub fn detect(header: &[u8]) -> Compression {
+		match header {
+			[0x1f, 0x8b, ..] => Compression::Gzip,
+			[b'B', b'Z', b'h', ..] => Compression::Bzip2,
+			[0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+			_ => Compression::None,
+		}
+	}
+}
+
+/// The input comes in chunks from a JS stream, so the decoders are fed the chunks (like `Write`) instead of reading them
+enum Decoder {
+	None,
+	// concatenated gzip members (`cat a.gz b.gz`) are decompressed too
+	Gzip(flate2::write::MultiGzDecoder<Vec<u8>>),
+	Bzip2(bzip2::write::BzDecoder<Vec<u8>>),
+	Zstd(Box<ZstdDecoder>),
+}
+
+impl Decoder {
+	fn new(compression: Compression) -> Decoder {
+		match compression {
+			Compression::None => Decoder::None,
+			Compression::Gzip => Decoder::Gzip(flate2::write::MultiGzDecoder::new(vec![])),
+			Compression::Bzip2 => Decoder::Bzip2(bzip2::write::BzDecoder::new(vec![])),
+			Compression::Zstd => Decoder::Zstd(Box::new(ZstdDecoder::new())),
+		}
+	}
+
+	/// Decompresses the next chunk of the input
+	fn push(&mut self, data: Vec<u8>) -> io::Result<Vec<u8>> {
+		match self {
+			Decoder::None => Ok(data),
+			Decoder::Gzip(d) => {
+				d.write_all(&data)?;
+				Ok(mem::take(d.get_mut()))
+			},
+			Decoder::Bzip2(d) => {
+				let mut data = &data[..];
+				let mut out = vec![];
+				while !data.is_empty() {
+					match d.write(data)? {
+						// end of the bzip2 stream, parallel bzip2 writes more of them
+						0 => {
+							out.append(&mut d.finish()?);
+							*d = bzip2::write::BzDecoder::new(vec![]);
+						},
+						len => data = &data[len..],
+					}
+				}
+				out.append(d.get_mut());
+				Ok(out)
+			},
+			Decoder::Zstd(d) => d.push(&data, false),
+		}
+	}
+
+	/// Flushes the rest of the output at the end of the input, fails when the input was truncated
+	fn finish(&mut self) -> io::Result<Vec<u8>> {
+		match self {
+			Decoder::None => Ok(vec![]),
+			Decoder::Gzip(d) => {
+				d.try_finish()?;
+				Ok(mem::take(d.get_mut()))
+			},
+			Decoder::Bzip2(d) => d.finish(),
+			Decoder::Zstd(d) => d.push(&[], true),
+		}
+	}
+}
+
+/// Frame header with all the optional fields
+const ZSTD_MAX_FRAME_HEADER: usize = 18;
+
+/// ruzstd decodes whole blocks, so the input is buffered until the block is complete
+struct ZstdDecoder {
+	decoder: FrameDecoder,
+	input: Vec<u8>,
+	buffer: Vec<u8>,
+	in_frame: bool,
+	/// remaining length of a skippable frame
+	skip: usize,
+}
+
+impl ZstdDecoder {
+	fn new() -> ZstdDecoder {
+		ZstdDecoder { decoder: FrameDecoder::new(), input: vec![], buffer: vec![0; 1 << 16], in_frame: false, skip: 0 }
+	}
+
+	fn push(&mut self, data: &[u8], last: bool) -> io::Result<Vec<u8>> {
+		let error = |e: FrameDecoderError| io::Error::new(io::ErrorKind::InvalidData, e);
+		self.input.extend_from_slice(data);
+		let mut out = vec![];
+		loop {
+			if self.skip > 0 {
+				let len = self.skip.min(self.input.len());
+				self.input.drain(..len);
+				self.skip -= len;
+				if self.skip > 0 {
+					break;
+				}
+			}
+			if !self.in_frame {
+				if self.input.is_empty() || (self.input.len() < ZSTD_MAX_FRAME_HEADER && !last) {
+					break;
+				}
+				let mut source = &self.input[..];
+				match self.decoder.reset(&mut source) {
+					Ok(()) => self.in_frame = true,
+					Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame { length, .. })) => self.skip = length as usize,
+					Err(e) => return Err(error(e)),
+				}
+				let header_len = self.input.len() - source.len();
+				self.input.drain(..header_len);
+				continue;
+			}
+
+			let (read, written) = self.decoder.decode_from_to(&self.input, &mut self.buffer).map_err(error)?;
+			if read > self.input.len() {
+				// ruzstd reports the 4 bytes of the frame checksum before they all arrived
+				break;
+			}
+			self.input.drain(..read);
+			out.extend_from_slice(&self.buffer[..written]);
+			if self.decoder.is_finished() && self.decoder.can_collect() == 0 {
+				self.in_frame = false;
+			} else if read == 0 && written == 0 {
+				break;
+			}
+		}
+
+		if last && (self.in_frame || self.skip > 0 || !self.input.is_empty()) {
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "zstd frame is truncated"));
+		}
+		Ok(out)
+	}
+}
+
+/// Decompresses the file if it's compressed by gzip, bzip2 or zstd, other files are passed through.
+/// A read error of the input is passed through and ends the stream.
+pub fn decompress<T: Stream<Item=Result<Vec<u8>, String>>>(bytes: T) -> impl Stream<Item=Result<Vec<u8>, String>> {
+	let mut decoder: Option<Decoder> = None;
+	let mut header: Vec<u8> = vec![];
+	let mut failed = false;
+
+	// None marks the end of the input
+	bytes.map(Some).chain(stream::iter([None])).filter_map(move |chunk| {
+		if failed {
+			return future::ready(None);
+		}
+		let chunk = match chunk.transpose() {
+			Ok(chunk) => chunk,
+			Err(e) => {
+				failed = true;
+				return future::ready(Some(Err(e)));
+			},
+		};
+		let data = match (&decoder, chunk) {
+			(Some(_), Some(chunk)) => Some(chunk),
+			(Some(_), None) => None,
+			(None, chunk) => {
+				let end = chunk.is_none();
+				header.extend(chunk.unwrap_or_default());
+				if header.len() < MAGIC_LENGTH && !end {
+					return future::ready(None);
+				}
+				let compression = Compression::detect(&header);
+				log::debug!("input compression: {:?}", compression);
+				decoder = Some(Decoder::new(compression));
+				if end { None } else { Some(mem::take(&mut header)) }
+			},
+		};
+		let d = decoder.as_mut().unwrap();
+		let result = match data {
+			Some(data) => d.push(data),
+			// the header of a short file was not pushed yet
+			None if !header.is_empty() => d.push(mem::take(&mut header)).and_then(|mut out| { out.append(&mut d.finish()?); Ok(out) }),
+			None => d.finish(),
+		};
+		let result = result.map_err(|e| {
+			failed = true;
+			format!("Could not decompress the input: {}", e)
+		});
+		future::ready(match result {
+			Ok(out) if out.is_empty() => None,
+			r => Some(r),
+		})
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+	const TEXT: &[u8] = b"line 1\nline 2\nline 3\n";
+
+	fn run(chunks: Vec<Result<Vec<u8>, String>>) -> Vec<Result<Vec<u8>, String>> {
+		block_on(decompress(stream::iter(chunks)).collect())
+	}
+
+	/// Feeds the data in chunks of `chunk_len` bytes and joins the output
+	fn decompress_all(data: &[u8], chunk_len: usize) -> Result<Vec<u8>, String> {
+		let out = run(data.chunks(chunk_len).map(|c| Ok(c.to_vec())).collect());
+		out.into_iter().collect::<Result<Vec<_>, _>>().map(|c| c.concat())
+	}
+
+	fn gzip(data: &[u8]) -> Vec<u8> {
+		let mut e = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+		e.write_all(data).unwrap();
+		e.finish().unwrap()
+	}
+
+	fn bzip2(data: &[u8]) -> Vec<u8> {
+		let mut e = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+		e.write_all(data).unwrap();
+		e.finish().unwrap()
+	}
+
+	#[test]
+	fn detects_the_compression() {
+		assert_eq!(Compression::detect(&gzip(TEXT)), Compression::Gzip);
+		assert_eq!(Compression::detect(&bzip2(TEXT)), Compression::Bzip2);
+		assert_eq!(Compression::detect(&compress_to_vec(TEXT, CompressionLevel::Fastest)), Compression::Zstd);
+		assert_eq!(Compression::detect(TEXT), Compression::None);
+		assert_eq!(Compression::detect(b"\x1f"), Compression::None);
+	}
+
+	#[test]
+	fn passes_plain_text_through() {
+		assert_eq!(decompress_all(TEXT, 1).unwrap(), TEXT);
+		assert_eq!(decompress_all(TEXT, 100).unwrap(), TEXT);
+		// shorter than the magic bytes
+		assert_eq!(decompress_all(b"ab", 1).unwrap(), b"ab");
+		assert_eq!(decompress_all(b"", 1).unwrap(), b"");
+	}
+
+	#[test]
+	fn decompresses_in_small_chunks() {
+		for chunk_len in [1, 3, 1000] {
+			assert_eq!(decompress_all(&gzip(TEXT), chunk_len).unwrap(), TEXT);
+			assert_eq!(decompress_all(&bzip2(TEXT), chunk_len).unwrap(), TEXT);
+			assert_eq!(decompress_all(&compress_to_vec(TEXT, CompressionLevel::Fastest), chunk_len).unwrap(), TEXT);
+		}
+	}
+
+	#[test]
+	fn decompresses_concatenated_files() {
+		let expected = [TEXT, b"more\n"].concat();
+		assert_eq!(decompress_all(&[gzip(TEXT), gzip(b"more\n")].concat(), 5).unwrap(), expected);
+		assert_eq!(decompress_all(&[bzip2(TEXT), bzip2(b"more\n")].concat(), 5).unwrap(), expected);
+		let zstd = [compress_to_vec(TEXT, CompressionLevel::Fastest), compress_to_vec(&b"more\n"[..], CompressionLevel::Fastest)].concat();
+		assert_eq!(decompress_all(&zstd, 5).unwrap(), expected);
+	}
+
+	#[test]
+	fn fails_on_truncated_files() {
+		for data in [gzip(TEXT), bzip2(TEXT), compress_to_vec(TEXT, CompressionLevel::Fastest)] {
+			let truncated = &data[..data.len() - 4];
+			let err = decompress_all(truncated, 7).unwrap_err();
+			assert!(err.starts_with("Could not decompress the input"), "{}", err);
+		}
+	}
+
+	#[test]
+	fn passes_read_errors_through_and_ends() {
+		let gz = gzip(TEXT);
+		let out = run(vec![ Ok(gz[..10].to_vec()), Err("Could not read the input: boom".to_owned()), Ok(gz[10..].to_vec()) ]);
+		assert_eq!(out.last(), Some(&Err("Could not read the input: boom".to_owned())));
+		assert_eq!(out.iter().filter(|r| r.is_err()).count(), 1);
+	}
+}
@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+
+use crate::parser::{GlobalTable, LogParser, FormatSpec, PRESETS};
+
+/// Number of lines used for the log format detection
+pub const SAMPLE_LINES: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectedFormat {
+	/// name of the preset
+	pub format: String,
+	/// share of the sampled lines which were parsed successfully
+	pub confidence: f64,
+	pub sample_lines: usize,
+}
+
+/// Tries all the built-in formats on the sample lines and picks the one which parses most of them.
+/// When more formats are equally good, the one earlier in `PRESETS` wins.
+pub fn detect_format(lines: &[String], ignore_query_string: bool) -> Option<DetectedFormat> {
+	let lines: Vec<&String> = lines.iter().filter(|l| !l.trim().is_empty()).take(SAMPLE_LINES).collect();
+	if lines.is_empty() {
+		return None;
+	}
+
+	let mut best: Option<DetectedFormat> = None;
+	for name in PRESETS {
+		let parser = LogParser::new(&FormatSpec::Preset(name.to_owned()), ignore_query_string).unwrap();
+		// don't pollute the real symbol table with the lines parsed by wrong formats
+		let mut table = GlobalTable::new();
+		let parsed = lines.iter().filter(|l| parser.parse_line(&mut table, l).is_ok()).count();
+		let confidence = parsed as f64 / lines.len() as f64;
+		log::debug!("format {}: {} of {} lines parsed", name, parsed, lines.len());
+		if best.as_ref().is_none_or(|b| confidence > b.confidence) {
+			best = Some(DetectedFormat { format: name.to_owned(), confidence, sample_lines: lines.len() });
+		}
+	}
+
+	best.filter(|b| b.confidence > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const KSP: &str = r#"2021-05-01 02:16:15 "1.1.1.1" "HTTP/1.0" GET ksp.mff.cuni.cz "/img/home.png" 200 0 0 "-" "Mozilla/5.0" "-" 61647 "image/png" "-""#;
+	const COMBINED: &str = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a/b.html HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 [en] (Win98; I ;Nav)""#;
+	const COMMON: &str = r#"::1 - - [10/Oct/2000:13:55:36 +0000] "HEAD /x HTTP/1.1" 304 -"#;
+	const CADDY: &str = r#"{"level":"info","ts":1646861401.52,"logger":"http.log.access","msg":"handled request","request":{"remote_ip":"10.0.0.1","client_ip":"1.2.3.4","remote_port":"41342","proto":"HTTP/2.0","method":"GET","host":"example.com","uri":"/a?b=1","headers":{"User-Agent":["curl/7.82.0"],"Referer":["https://example.com/"]}},"user_id":"frank","duration":0.0009,"size":10900,"status":404,"resp_headers":{"Content-Type":["text/html; charset=utf-8"],"Content-Encoding":["gzip"]}}"#;
+	const TRAEFIK: &str = r#"{"ClientHost":"1.2.3.4","ClientUsername":"-","DownstreamContentSize":1024,"DownstreamStatus":200,"RequestHost":"example.com","RequestMethod":"POST","RequestPath":"/form","RequestProtocol":"HTTP/1.1","StartUTC":"2021-05-01T02:16:15.123456789Z","request_User-Agent":"curl/7.82.0","downstream_Content-Type":"application/json"}"#;
+
+	fn detect(lines: &[&str]) -> Option<DetectedFormat> {
+		detect_format(&lines.iter().map(|&l| l.to_owned()).collect::<Vec<_>>(), true)
+	}
+
+	#[test]
+	fn picks_each_preset() {
+		for (line, format) in [(KSP, "ksp"), (COMBINED, "combined"), (COMMON, "common"), (CADDY, "caddy"), (TRAEFIK, "traefik")] {
+			let d = detect(&[line, "", line]).unwrap();
+			assert_eq!((d.format.as_str(), d.confidence, d.sample_lines), (format, 1.0, 2));
+		}
+	}
+
+	#[test]
+	fn reports_the_share_of_the_parsed_lines() {
+		let d = detect(&[COMBINED, COMBINED, COMBINED, "garbage"]).unwrap();
+		assert_eq!((d.format.as_str(), d.confidence), ("combined", 0.75));
+		// the sample is limited
+		let lines = vec![COMMON; SAMPLE_LINES * 2];
+		assert_eq!(detect(&lines).unwrap().sample_lines, SAMPLE_LINES);
+	}
+
+	#[test]
+	fn detects_nothing_in_unknown_logs() {
+		assert!(detect(&["garbage", "Oct 10 13:55:36 host sshd[1234]: Accepted publickey"]).is_none());
+		assert!(detect(&["", "  "]).is_none());
+		assert!(detect(&[]).is_none());
+	}
+}
@@ -0,0 +1,189 @@
+//! Properties of the sessions and requests which the usage stats can be grouped by
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{bot_filter, parser::{GlobalTable, no_dash}, session_analyzer::Session, stats::{calc_stats, make_inverse_core, StatsOptions, UsageStats}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+	Path,
+	Referer,
+	/// host name of the referer, `(direct)` when there is none
+	RefererDomain,
+	/// of the first request of the session
+	UserAgent,
+	/// browser name (`Firefox`, `Chrome`, ...) or `Bot`
+	UaFamily,
+	Domain,
+	/// only 2xx with the default `ClassifierConfig`, which ignores the other responses
+	StatusCode,
+	Method,
+	ContentType,
+	/// the `country` field of the log format, of the first request of the session
+	Country,
+}
+
+/// Usage over time grouped by the dimension.
+/// With `all_requests`, every page view is counted, otherwise only the starts of the sessions.
+pub fn usage_stats(sessions: &[Session], table: &GlobalTable, dimension: Dimension, opt: &StatsOptions, all_requests: bool) -> UsageStats {
+	// category of each id in the table of the dimension
+	let names: Vec<String> = match dimension {
+		Dimension::Path => table.path_list.clone(),
+		Dimension::Referer => names(&table.referer).map(str::to_owned).collect(),
+		Dimension::RefererDomain => names(&table.referer).map(referer_domain).collect(),
+		Dimension::UserAgent => names(&table.user_agent).map(str::to_owned).collect(),
+		Dimension::UaFamily => names(&table.user_agent).map(|ua| ua_family(ua).to_owned()).collect(),
+		Dimension::Domain => names(&table.domain).map(str::to_owned).collect(),
+		Dimension::StatusCode => {
+			let max_status = sessions.iter().flat_map(|s| &s.details).map(|d| d.status_code).max().unwrap_or(0);
+			(0..=max_status).map(|status| status.to_string()).collect()
+		},
+		Dimension::Method => names(&table.method).map(str::to_owned).collect(),
+		Dimension::ContentType => names(&table.content_type).map(str::to_owned).collect(),
+		Dimension::Country => names(&table.country).map(|c| if c.is_empty() { "(unknown)".to_owned() } else { c.to_uppercase() }).collect(),
+	};
+	let property: fn(&Session, usize) -> u32 = match dimension {
+		Dimension::Path => |s, i| s.actions[i],
+		Dimension::Referer | Dimension::RefererDomain => |s, i| s.details[i].referer,
+		Dimension::UserAgent | Dimension::UaFamily => |s, _i| s.user_agent,
+		Dimension::Domain => |s, i| s.details[i].domain,
+		Dimension::StatusCode => |s, i| s.details[i].status_code as u32,
+		Dimension::Method => |s, i| s.details[i].method,
+		Dimension::ContentType => |s, i| s.details[i].content_type,
+		Dimension::Country => |s, _i| s.country,
+	};
+	calc_stats(sessions, opt, all_requests, |s, i| names[property(s, i) as usize].as_str(), |name| name.to_string())
+}
+
+fn names(mapping: &HashMap<String, u32>) -> impl Iterator<Item = &str> {
+	let names = if mapping.is_empty() { vec![] } else { make_inverse_core(mapping, "") };
+	names.into_iter()
+}
+
+/// `https://www.google.com/search` -> `www.google.com`
+fn referer_domain(referer: &str) -> String {
+	let referer = no_dash(referer);
+	match referer.find("://") {
+		Some(scheme_end) => {
+			let host = referer[scheme_end + 3..].split(['/', '?', '#']).next().unwrap_or("");
+			host.rsplit('@').next().unwrap_or("").split(':').next().unwrap_or("").to_lowercase()
+		},
+		None if referer.is_empty() => "(direct)".to_owned(),
+		None => "(other)".to_owned(),
+	}
+}
+
+/// Browser name from the user agent. The order matters, most browsers claim to be the others too
+/// (Edge has `Chrome/` and `Safari/` in its user agent). Bots are the user agents of `BotRule::KnownCrawlers`,
+/// except the command line tools, which are on that list too.
+fn ua_family(ua: &str) -> &'static str {
+	let lowercase = ua.to_lowercase();
+	let tools = [
+		(&["curl/"][..], "curl"),
+		(&["wget/"], "Wget"),
+		(&["python"], "Python"),
+	];
+	let browsers = [
+		(&["edg/", "edge/", "edga/", "edgios/"][..], "Edge"),
+		(&["opr/", "opera"], "Opera"),
+		(&["samsungbrowser/"], "Samsung Internet"),
+		(&["yabrowser/"], "Yandex Browser"),
+		(&["firefox/", "fxios/"], "Firefox"),
+		(&["chrome/", "crios/", "chromium/"], "Chrome"),
+		(&["safari/"], "Safari"),
+		(&["msie ", "trident/"], "Internet Explorer"),
+	];
+	if ua.is_empty() || ua == "-" {
+		return "(none)";
+	}
+	let find = |families: &[(&[&str], &'static str)]| families.iter().find(|(patterns, _)| patterns.iter().any(|p| lowercase.contains(p))).map(|&(_, name)| name);
+	find(&tools)
+		.or_else(|| bot_filter::is_known_crawler(&lowercase).then_some("Bot"))
+		.or_else(|| find(&browsers))
+		.unwrap_or("Other")
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::DateTime;
+
+	use super::*;
+	use crate::{parser::get_or_add, session_analyzer::ActionDetails, session_key::Identity};
+
+	/// Session of the user agent with a page view for each of the `referers`, a minute apart
+	fn session(table: &mut GlobalTable, user_agent: &str, referers: &[&str]) -> Session {
+		let time = DateTime::from_timestamp(0, 0).unwrap();
+		let user_agent = get_or_add(&mut table.user_agent, user_agent);
+		let details: Vec<_> = referers.iter()
+			.map(|r| ActionDetails { status_code: 200, method: 0, domain: 0, referer: get_or_add(&mut table.referer, r), content_type: 0, bytes: 0 })
+			.collect();
+		Session {
+			identity: Identity::IpUserAgent { ip: 0, user_agent },
+			ip: 0, user_agent, referer: 0, country: 0,
+			start_time: time, end_time: time, last_request_time: time,
+			actions: vec![0; referers.len()],
+			access_times: (0..referers.len() as u32).map(|i| i * 60).collect(),
+			details,
+			parents: vec![],
+			total_requests: referers.len() as u32,
+			total_bytes: 0,
+		}
+	}
+
+	fn totals(stats: &UsageStats) -> Vec<(&str, u32)> {
+		let mut totals: Vec<_> = stats.rows.iter().map(|r| (r.category.as_str(), r.count.iter().sum())).collect();
+		totals.sort_unstable();
+		totals
+	}
+
+	#[test]
+	fn recognizes_the_browsers() {
+		for (ua, family) in [
+			("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0.0.0", "Edge"),
+			("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36", "Chrome"),
+			("Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0", "Firefox"),
+			("Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Mobile/15E148 Safari/604.1", "Safari"),
+			("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)", "Bot"),
+			("curl/8.4.0", "curl"),
+			("python-requests/2.31.0", "Python"),
+			("-", "(none)"),
+			("Lynx/2.9.0", "Other"),
+		] {
+			assert_eq!(ua_family(ua), family, "{}", ua);
+		}
+	}
+
+	#[test]
+	fn finds_the_referer_domains() {
+		assert_eq!(referer_domain("https://user@WWW.Google.com:443/search?q=x"), "www.google.com");
+		assert_eq!(referer_domain("android-app://com.slack/"), "com.slack");
+		assert_eq!(referer_domain("https://example.com?x=1"), "example.com");
+		assert_eq!(referer_domain("-"), "(direct)");
+		assert_eq!(referer_domain(""), "(direct)");
+		assert_eq!(referer_domain("/relative"), "(other)");
+	}
+
+	#[test]
+	fn counts_the_session_starts_or_all_requests() {
+		let mut table = GlobalTable::new();
+		let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+		let sessions = [
+			session(&mut table, firefox, &["https://www.google.com/search", "https://example.com/a", "https://example.com/b"]),
+			session(&mut table, firefox, &["https://www.google.com/", "https://example.com/a"]),
+			session(&mut table, "curl/8.4.0", &["-"]),
+		];
+		let opt = StatsOptions::new(60, 0, 10);
+		let starts = usage_stats(&sessions, &table, Dimension::RefererDomain, &opt, false);
+		assert!(starts.session_starts_only);
+		assert_eq!(totals(&starts), [("(direct)", 1), ("www.google.com", 2)]);
+		let all = usage_stats(&sessions, &table, Dimension::RefererDomain, &opt, true);
+		assert!(!all.session_starts_only);
+		assert_eq!(totals(&all), [("(direct)", 1), ("example.com", 3), ("www.google.com", 2)]);
+		assert_eq!((all.start_time, all.end_time), (0, 2));
+		// the user agent is the same for all the requests of a session
+		let families = usage_stats(&sessions, &table, Dimension::UaFamily, &opt, true);
+		assert_eq!(totals(&families), [("Firefox", 5), ("curl", 1)]);
+	}
+}
@@ -0,0 +1,357 @@
+//! Filter expressions selecting the sessions, e.g. `contains /h/** and not (ua *bot* or referer *google*)`.
+//!
+//! * `contains P`, `starts P` - some page / the first page of the session matches
+//! * `referer P`, `ua P` - referer / user agent of the first request matches
+//! * `status 404`, `status 4xx` - some page was returned with the status. The default `ClassifierConfig` ignores
+//!   the responses other than 2xx, the errors only get into the sessions with classifier rules which make them pages.
+//! * `after D`, `before D` - the session started at or after / before the date (`2021-06-01` in the local time, or RFC 3339)
+//! * `not`, `and`, `or` and parentheses
+//!
+//! Patterns are globs (`*` matches within a path segment, `**` any number of them), or regular expressions written as `re"..."`.
+//! Patterns with spaces or parentheses are quoted: `ua "*Mozilla/5.0 (X11*"`.
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use globset::GlobBuilder;
+use regex::Regex;
+
+use crate::{classifier::parse_status, parser::{local_to_utc, GlobalTable}, session_analyzer::Session, stats::make_inverse_core};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+	Open,
+	Close,
+	Word(String),
+	Quoted(String),
+	Regex(String),
+}
+
+impl std::fmt::Display for Token {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Token::Open => write!(f, "("),
+			Token::Close => write!(f, ")"),
+			Token::Word(w) => write!(f, "{}", w),
+			Token::Quoted(w) => write!(f, "\"{}\"", w),
+			Token::Regex(r) => write!(f, "re\"{}\"", r),
+		}
+	}
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+	let mut tokens = vec![];
+	let mut chars = expr.chars().peekable();
+	let quoted = |chars: &mut std::iter::Peekable<std::str::Chars>| -> Result<String, String> {
+		let mut s = String::new();
+		loop {
+			match chars.next() {
+				None => return Err(format!("Unclosed quote in the filter {}", expr)),
+				Some('"') => return Ok(s),
+				Some('\\') if matches!(chars.peek(), Some('"' | '\\')) => s.push(chars.next().unwrap()),
+				Some(c) => s.push(c),
+			}
+		}
+	};
+	while let Some(&c) = chars.peek() {
+		match c {
+			c if c.is_whitespace() => { chars.next(); },
+			'(' => { chars.next(); tokens.push(Token::Open) },
+			')' => { chars.next(); tokens.push(Token::Close) },
+			'"' => { chars.next(); tokens.push(Token::Quoted(quoted(&mut chars)?)) },
+			_ => {
+				let mut word = String::new();
+				while let Some(&c) = chars.peek() {
+					if c.is_whitespace() || c == '(' || c == ')' {
+						break;
+					}
+					chars.next();
+					if c == '"' && word == "re" {
+						word.clear();
+						break;
+					}
+					word.push(c);
+				}
+				if word.is_empty() {
+					tokens.push(Token::Regex(quoted(&mut chars)?));
+				} else {
+					tokens.push(Token::Word(word));
+				}
+			},
+		}
+	}
+	Ok(tokens)
+}
+
+enum Filter {
+	Not(Box<Filter>),
+	And(Box<Filter>, Box<Filter>),
+	Or(Box<Filter>, Box<Filter>),
+	/// matching path ids
+	Contains(Vec<bool>),
+	Starts(Vec<bool>),
+	/// matching referer ids
+	Referer(Vec<bool>),
+	/// matching user agent ids
+	UserAgent(Vec<bool>),
+	/// inclusive range
+	Status(u32, u32),
+	After(DateTime<Utc>),
+	Before(DateTime<Utc>),
+}
+
+struct Parser<'a> {
+	tokens: Vec<Token>,
+	position: usize,
+	table: &'a GlobalTable,
+	timezone: Tz,
+}
+
+impl Parser<'_> {
+	fn next(&mut self) -> Option<Token> {
+		let t = self.tokens.get(self.position).cloned();
+		self.position += 1;
+		t
+	}
+
+	fn peek_keyword(&self, keyword: &str) -> bool {
+		matches!(self.tokens.get(self.position), Some(Token::Word(w)) if w == keyword)
+	}
+
+	fn or(&mut self) -> Result<Filter, String> {
+		let mut f = self.and()?;
+		while self.peek_keyword("or") {
+			self.position += 1;
+			f = Filter::Or(Box::new(f), Box::new(self.and()?));
+		}
+		Ok(f)
+	}
+
+	fn and(&mut self) -> Result<Filter, String> {
+		let mut f = self.unary()?;
+		while self.peek_keyword("and") {
+			self.position += 1;
+			f = Filter::And(Box::new(f), Box::new(self.unary()?));
+		}
+		Ok(f)
+	}
+
+	fn unary(&mut self) -> Result<Filter, String> {
+		match self.next() {
+			Some(Token::Open) => {
+				let f = self.or()?;
+				match self.next() {
+					Some(Token::Close) => Ok(f),
+					_ => Err("Missing ) in the filter".to_owned()),
+				}
+			},
+			Some(Token::Word(w)) => match w.as_str() {
+				"not" => Ok(Filter::Not(Box::new(self.unary()?))),
+				"contains" | "starts" => {
+					let paths = self.pattern(&w, true, &self.table.path_list.iter().map(String::as_str).collect::<Vec<_>>())?;
+					Ok(if w == "contains" { Filter::Contains(paths) } else { Filter::Starts(paths) })
+				},
+				"referer" => Ok(Filter::Referer(self.pattern(&w, false, &names(&self.table.referer))?)),
+				"ua" => Ok(Filter::UserAgent(self.pattern(&w, false, &names(&self.table.user_agent))?)),
+				"status" => {
+					let (from, to) = parse_status(&self.argument(&w)?)?;
+					Ok(Filter::Status(from, to))
+				},
+				"after" => Ok(Filter::After(self.date(&w)?)),
+				"before" => Ok(Filter::Before(self.date(&w)?)),
+				_ => Err(format!("Unknown filter {}, expected contains, starts, referer, ua, status, after, before or not", w)),
+			},
+			Some(t) => Err(format!("Unexpected {} in the filter", t)),
+			None => Err("Unexpected end of the filter".to_owned()),
+		}
+	}
+
+	fn argument(&mut self, keyword: &str) -> Result<String, String> {
+		match self.next() {
+			Some(Token::Word(w) | Token::Quoted(w)) => Ok(w),
+			_ => Err(format!("Expected a value after {}", keyword)),
+		}
+	}
+
+	/// which of the `values` match the pattern after the keyword
+	fn pattern(&mut self, keyword: &str, is_path: bool, values: &[&str]) -> Result<Vec<bool>, String> {
+		match self.next() {
+			Some(Token::Regex(r)) => {
+				let r = Regex::new(&r).map_err(|e| format!("Invalid pattern {}: {}", r, e))?;
+				Ok(values.iter().map(|v| r.is_match(v)).collect())
+			},
+			Some(Token::Word(p) | Token::Quoted(p)) => {
+				// the paths are stored without the trailing slash, see `GlobalTable::add_path`
+				let glob = if is_path { p.strip_suffix('/').unwrap_or(&p) } else { &p };
+				let glob = GlobBuilder::new(glob).literal_separator(is_path).build()
+					.map_err(|e| format!("Invalid pattern {}: {}", p, e))?.compile_matcher();
+				Ok(values.iter().map(|v| glob.is_match(v)).collect())
+			},
+			_ => Err(format!("Expected a pattern after {}", keyword)),
+		}
+	}
+
+	fn date(&mut self, keyword: &str) -> Result<DateTime<Utc>, String> {
+		let d = self.argument(keyword)?;
+		match NaiveDate::parse_from_str(&d, "%Y-%m-%d") {
+			Ok(date) => Ok(local_to_utc(&self.timezone, date.and_hms_opt(0, 0, 0).unwrap())),
+			Err(_) => DateTime::parse_from_rfc3339(&d).map(|t| t.to_utc())
+				.map_err(|_| format!("Invalid date {}, expected e.g. 2021-06-01 or 2021-06-01T12:00:00Z", d)),
+		}
+	}
+}
+
+fn names(mapping: &HashMap<String, u32>) -> Vec<&str> {
+	if mapping.is_empty() { vec![] } else { make_inverse_core(mapping, "") }
+}
+
+pub struct SessionFilter {
+	filter: Option<Filter>,
+}
+
+impl SessionFilter {
+	/// The table must contain all the paths of the sessions, the empty expression matches all the sessions.
+	/// Dates without the time are in `timezone`.
+	pub fn new(expr: &str, table: &GlobalTable, timezone: Tz) -> Result<SessionFilter, String> {
+		let tokens = tokenize(expr)?;
+		if tokens.is_empty() {
+			return Ok(SessionFilter { filter: None });
+		}
+		let mut parser = Parser { tokens, position: 0, table, timezone };
+		let filter = parser.or()?;
+		if let Some(t) = parser.tokens.get(parser.position) {
+			return Err(format!("Unexpected {} in the filter, expected and or or", t));
+		}
+		Ok(SessionFilter { filter: Some(filter) })
+	}
+
+	pub fn matches(&self, s: &Session) -> bool {
+		self.filter.as_ref().is_none_or(|f| f.matches(s))
+	}
+}
+
+impl Filter {
+	fn matches(&self, s: &Session) -> bool {
+		let contains = |ids: &[bool], id: u32| ids.get(id as usize).copied().unwrap_or(false);
+		match self {
+			Filter::Not(f) => !f.matches(s),
+			Filter::And(a, b) => a.matches(s) && b.matches(s),
+			Filter::Or(a, b) => a.matches(s) || b.matches(s),
+			Filter::Contains(paths) => s.actions.iter().any(|&a| contains(paths, a)),
+			Filter::Starts(paths) => s.actions.first().is_some_and(|&a| contains(paths, a)),
+			Filter::Referer(referers) => contains(referers, s.referer),
+			Filter::UserAgent(user_agents) => contains(user_agents, s.user_agent),
+			&Filter::Status(from, to) => s.details.iter().any(|d| (from..=to).contains(&(d.status_code as u32))),
+			Filter::After(t) => s.start_time >= *t,
+			Filter::Before(t) => s.start_time < *t,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{parser::get_or_add, session_analyzer::ActionDetails, session_key::Identity};
+
+	fn word(w: &str) -> Token {
+		Token::Word(w.to_owned())
+	}
+
+	/// Session starting at `start` (RFC 3339) with the `(path, status)` pages
+	fn session(table: &mut GlobalTable, start: &str, referer: &str, user_agent: &str, pages: &[(&str, u16)]) -> Session {
+		let time = DateTime::parse_from_rfc3339(start).unwrap().to_utc();
+		let details = ActionDetails { status_code: 200, method: 0, domain: 0, referer: 0, content_type: 0, bytes: 0 };
+		Session {
+			identity: Identity::IpUserAgent { ip: 0, user_agent: 0 },
+			ip: 0,
+			user_agent: get_or_add(&mut table.user_agent, user_agent),
+			referer: get_or_add(&mut table.referer, referer),
+			country: 0,
+			start_time: time, end_time: time, last_request_time: time,
+			actions: pages.iter().map(|&(p, _)| table.add_path(p)).collect(),
+			access_times: vec![0; pages.len()],
+			details: pages.iter().map(|&(_, status_code)| ActionDetails { status_code, ..details }).collect(),
+			parents: vec![],
+			total_requests: pages.len() as u32,
+			total_bytes: 0,
+		}
+	}
+
+	fn sessions(table: &mut GlobalTable) -> Vec<Session> {
+		vec![
+			session(table, "2021-06-01T10:00:00Z", "https://www.google.com/", "Mozilla/5.0 (X11; Linux) Firefox/90.0", &[("/", 200), ("/h/ulohy/", 200)]),
+			session(table, "2021-05-31T23:30:00Z", "", "Googlebot/2.1", &[("/h/", 200), ("/missing", 404)]),
+			session(table, "2021-06-02T10:00:00Z", "https://example.com/a", "curl/8.0", &[("/about", 500)]),
+		]
+	}
+
+	/// Indices of the sessions matching the filter
+	fn matching(expr: &str) -> Result<Vec<usize>, String> {
+		let mut table = GlobalTable::new();
+		let sessions = sessions(&mut table);
+		let filter = SessionFilter::new(expr, &table, chrono_tz::Europe::Prague)?;
+		Ok(sessions.iter().enumerate().filter(|(_, s)| filter.matches(s)).map(|(i, _)| i).collect())
+	}
+
+	#[test]
+	fn tokenizes_words_quotes_and_regexes() {
+		assert_eq!(tokenize(r#"contains /h/** and not(ua "*Mozilla/5.0 (X11*" or referer re"goo+gle\.")"#).unwrap(), [
+			word("contains"), word("/h/**"), word("and"), word("not"), Token::Open, word("ua"), Token::Quoted("*Mozilla/5.0 (X11*".to_owned()),
+			word("or"), word("referer"), Token::Regex(r"goo+gle\.".to_owned()), Token::Close,
+		]);
+		assert_eq!(tokenize(r#"ua "a \"b\" \\ \c""#).unwrap(), [word("ua"), Token::Quoted(r#"a "b" \ \c"#.to_owned())]);
+		assert_eq!(tokenize("  ").unwrap(), []);
+		assert!(tokenize(r#"ua "abc"#).unwrap_err().starts_with("Unclosed quote"));
+		assert!(tokenize(r#"ua re"abc"#).unwrap_err().starts_with("Unclosed quote"));
+	}
+
+	#[test]
+	fn matches_the_conditions() {
+		assert_eq!(matching("").unwrap(), [0, 1, 2]);
+		assert_eq!(matching("contains /h/*").unwrap(), [0]);
+		assert_eq!(matching("contains /h/ulohy/").unwrap(), [0]);
+		assert_eq!(matching("contains /h").unwrap(), [1]);
+		assert_eq!(matching("contains /**/ulohy").unwrap(), [0]);
+		assert_eq!(matching("starts /h").unwrap(), [1]);
+		assert_eq!(matching("referer *google*").unwrap(), [0]);
+		assert_eq!(matching(r#"ua re"^Mozilla/.*Firefox""#).unwrap(), [0]);
+		assert_eq!(matching(r#"ua "*(X11*""#).unwrap(), [0]);
+		assert_eq!(matching("status 404").unwrap(), [1]);
+		assert_eq!(matching("status 4xx").unwrap(), [1]);
+		assert_eq!(matching("status 5xx").unwrap(), [2]);
+	}
+
+	#[test]
+	fn reads_dates_in_the_timezone() {
+		// midnight in Prague is 22:00 UTC in the summer
+		assert_eq!(matching("after 2021-06-01").unwrap(), [0, 1, 2]);
+		assert_eq!(matching("before 2021-06-01").unwrap(), Vec::<usize>::new());
+		assert_eq!(matching("after 2021-06-01T00:00:00Z and before 2021-06-02").unwrap(), [0]);
+	}
+
+	#[test]
+	fn combines_the_conditions() {
+		assert_eq!(matching("not contains /h/**").unwrap(), [1, 2]);
+		// and binds stronger than or
+		assert_eq!(matching("status 5xx or contains /h and status 4xx").unwrap(), [1, 2]);
+		assert_eq!(matching("(status 5xx or contains /h) and status 4xx").unwrap(), [1]);
+		assert_eq!(matching("contains /h/** and not (ua *bot* or referer *google*)").unwrap(), Vec::<usize>::new());
+		assert_eq!(matching("not not starts /h").unwrap(), [1]);
+	}
+
+	#[test]
+	fn reports_syntax_errors() {
+		let error = |expr: &str| matching(expr).unwrap_err();
+		assert_eq!(error("contains"), "Expected a pattern after contains");
+		assert_eq!(error("status"), "Expected a value after status");
+		assert!(error("visits 3").starts_with("Unknown filter visits"));
+		assert_eq!(error("(contains /a"), "Missing ) in the filter");
+		assert_eq!(error("contains /a contains /b"), "Unexpected contains in the filter, expected and or or");
+		assert_eq!(error("contains /a and"), "Unexpected end of the filter");
+		assert_eq!(error(")"), "Unexpected ) in the filter");
+		assert!(error("status 4x").starts_with("Invalid status 4x"));
+		assert!(error("after yesterday").starts_with("Invalid date yesterday"));
+		assert!(error("contains /a[").starts_with("Invalid pattern /a["));
+		assert!(error(r#"ua re"(""#).starts_with("Invalid pattern ("));
+	}
+}
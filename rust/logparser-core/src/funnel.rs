@@ -0,0 +1,189 @@
+//! Conversion funnels: how many sessions went through the pages in the given order
+use globset::GlobBuilder;
+use serde::{Serialize, Deserialize};
+
+use crate::{parser::GlobalTable, session_analyzer::Session};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunnelOptions {
+	/// glob pattern of the path of each step, `*` matches within a segment and `**` any number of segments (`/signup/**`)
+	pub steps: Vec<String>,
+	/// the steps must directly follow each other, otherwise other pages may be visited between them
+	#[serde(default)]
+	pub strict: bool,
+	/// maximal number of seconds between two steps
+	#[serde(default)]
+	pub max_step_time: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunnelStep {
+	pub pattern: String,
+	/// sessions which reached this step
+	pub sessions: u32,
+	/// sessions which reached this step, but not the next one
+	pub drop_off: u32,
+	/// median seconds from this step to the next one, of the sessions which reached it
+	pub median_time_to_next: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Funnel {
+	pub steps: Vec<FunnelStep>,
+}
+
+pub fn calc_funnel(sessions: &[Session], table: &GlobalTable, opt: &FunnelOptions) -> Result<Funnel, String> {
+	// step_paths[step][path id]
+	let step_paths = opt.steps.iter().map(|pattern| {
+		// the paths are stored without the trailing slash, see `GlobalTable::add_path`
+		let glob = GlobBuilder::new(pattern.strip_suffix('/').unwrap_or(pattern)).literal_separator(true).build()
+			.map_err(|e| format!("Invalid step pattern {}: {}", pattern, e))?.compile_matcher();
+		Ok(table.path_list.iter().map(|p| glob.is_match(p)).collect::<Vec<_>>())
+	}).collect::<Result<Vec<_>, String>>()?;
+
+	let mut reached = vec![0u32; opt.steps.len()];
+	let mut step_times: Vec<Vec<u32>> = vec![vec![]; opt.steps.len()];
+	for s in sessions {
+		let times = session_steps(s, &step_paths, opt);
+		for (step, time) in times.iter().enumerate() {
+			reached[step] += 1;
+			if step > 0 {
+				step_times[step - 1].push(time.saturating_sub(times[step - 1]));
+			}
+		}
+	}
+
+	let steps = opt.steps.iter().enumerate().map(|(i, pattern)| {
+		let times = &mut step_times[i];
+		times.sort_unstable();
+		FunnelStep {
+			pattern: pattern.clone(),
+			sessions: reached[i],
+			drop_off: reached[i] - reached.get(i + 1).copied().unwrap_or(0),
+			median_time_to_next: times.get(times.len() / 2).copied(),
+		}
+	}).collect();
+	Ok(Funnel { steps })
+}
+
+/// Access times of the steps reached by the session, along the path reaching the most of them
+fn session_steps(s: &Session, step_paths: &[Vec<bool>], opt: &FunnelOptions) -> Vec<u32> {
+	let n = s.actions.len().min(s.access_times.len());
+	let matches = |step: usize, i: usize| step_paths[step][s.actions[i] as usize];
+	// previous[step][i]: the action can be the step, and the index of the action of the previous step
+	let mut previous: Vec<Vec<Option<usize>>> = vec![ (0..n).map(|i| (!step_paths.is_empty() && matches(0, i)).then_some(i)).collect() ];
+	for step in 1..step_paths.len() {
+		let last = &previous[step - 1];
+		let current: Vec<Option<usize>> = (0..n).map(|i| {
+			if !matches(step, i) {
+				return None;
+			}
+			let candidates = if opt.strict { i.saturating_sub(1)..i } else { 0..i };
+			// the latest previous step, it's the closest in time
+			candidates.rev().find(|&j| last[j].is_some() &&
+				opt.max_step_time.is_none_or(|max| s.access_times[i].saturating_sub(s.access_times[j]) <= max))
+		}).collect();
+		if current.iter().all(Option::is_none) {
+			break;
+		}
+		previous.push(current);
+	}
+
+	let Some(mut i) = previous.last().and_then(|last| last.iter().position(Option::is_some)) else {
+		return vec![];
+	};
+	let mut times = vec![];
+	for step in (0..previous.len()).rev() {
+		times.push(s.access_times[i]);
+		i = previous[step][i].unwrap();
+	}
+	times.reverse();
+	times
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::DateTime;
+
+	use super::*;
+	use crate::session_key::Identity;
+
+	/// Session of the `(path, second)` page views
+	fn session(table: &mut GlobalTable, pages: &[(&str, u32)]) -> Session {
+		let time = DateTime::from_timestamp(0, 0).unwrap();
+		Session {
+			identity: Identity::IpUserAgent { ip: 0, user_agent: 0 },
+			ip: 0, user_agent: 0, referer: 0, country: 0,
+			start_time: time, end_time: time, last_request_time: time,
+			actions: pages.iter().map(|&(p, _)| table.add_path(p)).collect(),
+			access_times: pages.iter().map(|&(_, t)| t).collect(),
+			details: vec![],
+			parents: vec![],
+			total_requests: pages.len() as u32,
+			total_bytes: 0,
+		}
+	}
+
+	fn options(steps: &[&str]) -> FunnelOptions {
+		FunnelOptions { steps: steps.iter().map(|&s| s.to_owned()).collect(), strict: false, max_step_time: None }
+	}
+
+	fn reached(f: &Funnel) -> Vec<u32> {
+		f.steps.iter().map(|s| s.sessions).collect()
+	}
+
+	#[test]
+	fn counts_the_sessions_of_each_step() {
+		let mut table = GlobalTable::new();
+		let sessions = [
+			session(&mut table, &[("/", 0), ("/signup", 10), ("/signup/done/", 40)]),
+			session(&mut table, &[("/", 0), ("/signup", 20)]),
+			session(&mut table, &[("/", 0), ("/about", 5)]),
+			session(&mut table, &[("/signup/done", 0)]),
+		];
+		let f = calc_funnel(&sessions, &table, &options(&["/", "/signup", "/signup/done/"])).unwrap();
+		assert_eq!(reached(&f), [3, 2, 1]);
+		assert_eq!(f.steps.iter().map(|s| s.drop_off).collect::<Vec<_>>(), [1, 1, 1]);
+		assert_eq!(f.steps.iter().map(|s| s.median_time_to_next).collect::<Vec<_>>(), [Some(20), Some(30), None]);
+	}
+
+	#[test]
+	fn requires_the_order_of_the_steps() {
+		let mut table = GlobalTable::new();
+		let sessions = [session(&mut table, &[("/b", 0), ("/a", 10)])];
+		assert_eq!(reached(&calc_funnel(&sessions, &table, &options(&["/a", "/b"])).unwrap()), [1, 0]);
+	}
+
+	#[test]
+	fn strict_funnel_allows_no_pages_between_the_steps() {
+		let mut table = GlobalTable::new();
+		let sessions = [session(&mut table, &[("/a", 0), ("/x", 10), ("/b", 20)])];
+		let opt = options(&["/a", "/b"]);
+		assert_eq!(reached(&calc_funnel(&sessions, &table, &opt).unwrap()), [1, 1]);
+		assert_eq!(reached(&calc_funnel(&sessions, &table, &FunnelOptions { strict: true, ..opt }).unwrap()), [1, 0]);
+	}
+
+	#[test]
+	fn takes_the_latest_previous_step_within_the_time_limit() {
+		let mut table = GlobalTable::new();
+		let sessions = [
+			session(&mut table, &[("/a", 0), ("/b", 100)]),
+			session(&mut table, &[("/a", 0), ("/a", 50), ("/b", 100)]),
+		];
+		let f = calc_funnel(&sessions, &table, &FunnelOptions { max_step_time: Some(60), ..options(&["/a", "/b"]) }).unwrap();
+		assert_eq!(reached(&f), [2, 1]);
+		assert_eq!(f.steps[0].median_time_to_next, Some(50));
+	}
+
+	#[test]
+	fn matches_glob_steps() {
+		let mut table = GlobalTable::new();
+		let sessions = [
+			session(&mut table, &[("/blog/x", 0), ("/cart", 10)]),
+			session(&mut table, &[("/blog/x/y", 0), ("/cart", 10)]),
+		];
+		assert_eq!(reached(&calc_funnel(&sessions, &table, &options(&["/blog/*", "/cart"])).unwrap()), [1, 1]);
+		assert_eq!(reached(&calc_funnel(&sessions, &table, &options(&["/blog/**", "/cart"])).unwrap()), [2, 2]);
+		assert!(calc_funnel(&sessions, &table, &options(&["/blog/[x"])).unwrap_err().starts_with("Invalid step pattern /blog/[x"));
+	}
+}
@@ -0,0 +1,219 @@
+//! Target independent part of the log analysis: parsing, sessions and the statistics.
+//! Messages are reported through the `log` crate, the application chooses where they go.
+pub mod parser;
+pub mod decompress;
+pub mod merge;
+pub mod bot_filter;
+pub mod detect;
+pub mod parse_error;
+pub mod presets;
+pub mod streamutil;
+pub mod session_analyzer;
+pub mod session_key;
+pub mod navigation;
+pub mod classifier;
+pub mod rewrite;
+pub mod dimension;
+pub mod funnel;
+pub mod filter;
+pub mod session_stats;
+pub mod visitors;
+pub mod snapshot;
+pub mod stats;
+
+use futures::{Stream, StreamExt, stream};
+use serde::{Serialize, Deserialize};
+use session_analyzer::{Session, Sessionizer, SessionOptions};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadOptions {
+	/// built-in format name (or `auto`) or a custom format definition
+	pub format: parser::FormatSpec,
+	pub ignore_query_string: bool,
+	/// session is closed after this number of seconds of inactivity
+	pub max_age: u32,
+	/// loading fails when more lines can not be parsed
+	#[serde(default)]
+	pub max_errors: Option<u64>,
+	/// zone of the logs which don't include the UTC offset, UTC by default
+	#[serde(default)]
+	pub timezone: Option<chrono_tz::Tz>,
+	/// lines of a file which are out of order by less than this number of seconds are sorted
+	#[serde(default = "default_reorder_window")]
+	pub reorder_window: u32,
+	/// the known crawlers and the sessions with more than 40 pages are excluded by default.
+	/// The graph used to also skip the sessions with less than 7 requests, add the `no_assets` rule to exclude the scrapers.
+	#[serde(default)]
+	pub bot_filter: bot_filter::BotFilterConfig,
+	/// how the requests are joined into sessions, ip + user agent by default
+	#[serde(default)]
+	pub session_key: session_key::SessionKeyConfig,
+	/// attach each page to the page in its referer (see `navigation`) instead of making one sequence
+	#[serde(default)]
+	pub referer_tree: bool,
+	/// which requests are page views and which are assets
+	#[serde(default)]
+	pub classifier: classifier::ClassifierConfig,
+	/// the sessions still active at the end of the logs are left open, so that the logs of the next load continue them (see `flush_sessions`)
+	#[serde(default)]
+	pub keep_open_sessions: bool,
+}
+
+fn default_reorder_window() -> u32 { merge::DEFAULT_REORDER_WINDOW }
+
+/// Number of the merged lines passed to `get_sessions` at once, the symbol table only grows between them
+const MERGE_CHUNK: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadSummary {
+	/// name of the used format, `custom` for the user defined format
+	pub format: String,
+	/// when the format was detected automatically, share of the sample lines it parsed
+	pub detection_confidence: Option<f64>,
+	pub lines: parse_error::ParseStats,
+	/// lines which came later than the reorder window allows
+	pub out_of_order_lines: u64,
+	/// sessions excluded by each bot rule
+	pub bots: Vec<bot_filter::BotRuleStats>,
+	/// sessions left open for the next load, see `LoadOptions::keep_open_sessions`
+	pub open_sessions: u32,
+}
+
+pub struct LoadResult {
+	pub sessions: Vec<Session>,
+	pub summary: LoadSummary,
+}
+
+/// Parses the log files (one byte stream per file, possibly compressed, a read error fails the load) and splits the requests into sessions. Bots are filtered out.
+/// The files may be in any order and overlap in time, their lines are merged by time.
+/// The sessions open from the previous load are continued, the logs must not be older than them.
+pub async fn load_sessions<S: Stream<Item=Result<Vec<u8>, String>>>(
+	input_streams: Vec<S>,
+	options: &LoadOptions,
+	symbol_table: &mut parser::GlobalTable,
+	sessionizer: &mut Sessionizer,
+) -> Result<LoadResult, String> {
+	let mut line_streams: Vec<_> = input_streams.into_iter().map(|s| Box::pin(streamutil::bytes_to_lines(decompress::decompress(s)))).collect();
+
+	// read a few lines ahead, they are needed for the format detection
+	let mut first_lines: Vec<Vec<String>> = vec![vec![]; line_streams.len()];
+	let (format, detection_confidence) = match &options.format {
+		parser::FormatSpec::Preset(name) if name == parser::AUTO_FORMAT => {
+			for (stream, first_lines) in line_streams.iter_mut().zip(first_lines.iter_mut()) {
+				while first_lines.len() < detect::SAMPLE_LINES {
+					match stream.next().await {
+						Some(lines) => first_lines.extend(lines?),
+						None => break
+					}
+				}
+			}
+			let sample: Vec<String> = first_lines.iter().flatten().take(detect::SAMPLE_LINES).cloned().collect();
+			let detected = detect::detect_format(&sample, options.ignore_query_string)
+				.ok_or("Could not detect the log format")?;
+			log::info!("Detected log format {} ({:.0}% of {} lines parsed)", detected.format, detected.confidence * 100.0, detected.sample_lines);
+			(parser::FormatSpec::Preset(detected.format), Some(detected.confidence))
+		},
+		f => (f.clone(), None)
+	};
+	let parser = parser::LogParser::new(&format, options.ignore_query_string)?
+		.with_timezone(options.timezone.unwrap_or(chrono_tz::Tz::UTC));
+	let bot_filter = bot_filter::BotFilter::new(&options.bot_filter)?;
+	let mut classifier = classifier::Classifier::new(&options.classifier)?;
+	sessionizer.set_options(SessionOptions { session_key: options.session_key.clone(), referer_tree: options.referer_tree })?;
+	let mut summary = LoadSummary {
+		format: match &format { parser::FormatSpec::Preset(name) => name.clone(), parser::FormatSpec::Custom(_) => "custom".to_owned() },
+		detection_confidence,
+		lines: parse_error::ParseStats::default(),
+		out_of_order_lines: 0,
+		bots: vec![],
+		open_sessions: 0,
+	};
+	let stats = &mut summary.lines;
+
+	let mut line_streams: Vec<_> = line_streams.into_iter().zip(first_lines).map(|(s, first_lines)| stream::iter([Ok(first_lines)]).chain(s)).collect();
+	let mut reorder: Vec<_> = line_streams.iter().map(|_| merge::ReorderBuffer::new(options.reorder_window)).collect();
+	let mut merged = merge::TimeMerge::new(line_streams.len());
+	let mut key = options.session_key.build();
+	let mut sessions: Vec<Session> = vec![];
+	let mut chunk: Vec<parser::LogLine> = Vec::with_capacity(MERGE_CHUNK);
+	// the files are read only as far as the merge needs them, the merged lines are split into sessions in chunks
+	loop {
+		if let Some(file) = merged.needs_input() {
+			let mut parsed = vec![];
+			match line_streams[file].next().await {
+				Some(lines) => for line in lines?.iter() {
+					stats.total_lines += 1;
+
+					if line.is_empty() {
+						stats.skipped_lines += 1;
+						continue
+					}
+
+					match parser.parse_line(symbol_table, line) {
+						Ok(l) => {
+							stats.parsed_lines += 1;
+							reorder[file].push(l, &mut parsed)
+						},
+						Err(e) => {
+							log::debug!("Could not parse {}: {}", line, e);
+							stats.add_error(line, e);
+							if let Some(max) = options.max_errors.filter(|&max| stats.error_count > max) {
+								let first = &stats.samples[0];
+								return Err(format!("More than {} lines could not be parsed ({}), the first one failed with {}: {}",
+									max, stats.describe_errors(), first.error, first.line));
+							}
+						}
+					}
+				},
+				None => {
+					reorder[file].finish(&mut parsed);
+					merged.finish(file);
+				},
+			}
+			merged.push(file, parsed);
+			continue;
+		}
+
+		let line = merged.pop();
+		let end = line.is_none();
+		chunk.extend(line);
+		if chunk.len() >= MERGE_CHUNK || (end && !chunk.is_empty()) {
+			sessions.extend(session_analyzer::get_sessions(sessionizer, symbol_table, &chunk, options.max_age, key.as_mut(), options.referer_tree, &mut classifier));
+			chunk.clear();
+		}
+		if end {
+			break;
+		}
+	}
+	summary.out_of_order_lines = reorder.iter().map(|r| r.late_lines).sum();
+	if stats.error_count > 0 {
+		log::warn!("{} of {} lines could not be parsed: {}", stats.error_count, stats.total_lines, stats.describe_errors());
+	}
+	if summary.out_of_order_lines > 0 {
+		log::warn!("{} lines are out of order by more than {}s, they got the time of the previous line", summary.out_of_order_lines, options.reorder_window);
+	}
+
+	if !options.keep_open_sessions {
+		sessions.extend(sessionizer.flush());
+	}
+	summary.open_sessions = sessionizer.len() as u32;
+	log::info!("Sessions (unfiltered): {}", sessions.len());
+	summary.bots = bot_filter.filter(&mut sessions, symbol_table);
+	for b in summary.bots.iter().filter(|b| b.sessions > 0) {
+		log::info!("Bots ({}): {} sessions", b.rule, b.sessions);
+	}
+	log::info!("Sessions (filtered): {}", sessions.len());
+	log::info!("Session actions: {}", sessions.iter().map(|s| s.actions.len()).sum::<usize>());
+	if summary.open_sessions > 0 {
+		log::info!("Sessions left open: {}", summary.open_sessions);
+	}
+	Ok(LoadResult { sessions, summary })
+}
+
+/// Closes the sessions left open by the previous loads (see `LoadOptions::keep_open_sessions`), bots are filtered out by `options.bot_filter`
+pub fn flush_sessions(sessionizer: &mut Sessionizer, options: &LoadOptions, symbol_table: &parser::GlobalTable) -> Result<Vec<Session>, String> {
+	let bot_filter = bot_filter::BotFilter::new(&options.bot_filter)?;
+	let mut sessions = sessionizer.flush();
+	bot_filter.filter(&mut sessions, symbol_table);
+	Ok(sessions)
+}
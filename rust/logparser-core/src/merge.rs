@@ -0,0 +1,202 @@
+//! Ordering of the log lines by time. `session_analyzer::get_sessions` expects the time to only move forward,
+//! but the lines of one file may be slightly shuffled (they are written when the request finishes)
+//! and rotated files or logs of several backends overlap.
+use std::{cmp::{Ordering, Reverse}, collections::{BinaryHeap, VecDeque}};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::parser::LogLine;
+
+/// Lines are sorted in this window (in seconds) by default
+pub const DEFAULT_REORDER_WINDOW: u32 = 60;
+
+/// Line ordered by time, then by the position in the input
+struct Timed(DateTime<Utc>, u64, LogLine);
+
+impl PartialEq for Timed {
+	fn eq(&self, other: &Self) -> bool {
+		(self.0, self.1) == (other.0, other.1)
+	}
+}
+impl Eq for Timed {}
+impl PartialOrd for Timed {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+impl Ord for Timed {
+	fn cmp(&self, other: &Self) -> Ordering {
+		(self.0, self.1).cmp(&(other.0, other.1))
+	}
+}
+
+/// Sorts the lines of one file which are out of order by less than `window`.
+/// Lines which come even later get the time of the last written line (so that the time never goes back)
+/// and are counted in `late_lines`.
+pub struct ReorderBuffer {
+	window: TimeDelta,
+	heap: BinaryHeap<Reverse<Timed>>,
+	counter: u64,
+	/// the newest time seen so far
+	newest: Option<DateTime<Utc>>,
+	/// time of the last line written to the output
+	emitted: Option<DateTime<Utc>>,
+	pub late_lines: u64,
+}
+
+impl ReorderBuffer {
+	pub fn new(window_sec: u32) -> ReorderBuffer {
+		ReorderBuffer { window: TimeDelta::seconds(window_sec as i64), heap: BinaryHeap::new(), counter: 0, newest: None, emitted: None, late_lines: 0 }
+	}
+
+	pub fn push(&mut self, line: LogLine, out: &mut Vec<LogLine>) {
+		if let Some(emitted) = self.emitted.filter(|&t| line.time < t) {
+			self.late_lines += 1;
+			out.push(LogLine { time: emitted, ..line });
+			return;
+		}
+		let newest = self.newest.map_or(line.time, |t| t.max(line.time));
+		self.newest = Some(newest);
+		self.counter += 1;
+		self.heap.push(Reverse(Timed(line.time, self.counter, line)));
+
+		while let Some(Reverse(Timed(time, _, _))) = self.heap.peek() {
+			if *time > newest - self.window {
+				break;
+			}
+			self.pop(out);
+		}
+	}
+
+	/// Writes out the rest of the buffered lines
+	pub fn finish(&mut self, out: &mut Vec<LogLine>) {
+		while !self.heap.is_empty() {
+			self.pop(out);
+		}
+	}
+
+	fn pop(&mut self, out: &mut Vec<LogLine>) {
+		let Reverse(Timed(time, _, line)) = self.heap.pop().unwrap();
+		self.emitted = Some(time);
+		out.push(line);
+	}
+}
+
+/// k-way merge of the files which are already sorted by time (by `ReorderBuffer`), equal times are taken from the earlier file first.
+/// The files are read lazily: a line is merged only when every unfinished file has some lines buffered, otherwise `needs_input` says which file to read.
+pub struct TimeMerge {
+	files: Vec<VecDeque<LogLine>>,
+	finished: Vec<bool>,
+}
+
+impl TimeMerge {
+	pub fn new(file_count: usize) -> TimeMerge {
+		TimeMerge { files: (0..file_count).map(|_| VecDeque::new()).collect(), finished: vec![false; file_count] }
+	}
+
+	pub fn push(&mut self, file: usize, lines: impl IntoIterator<Item=LogLine>) {
+		self.files[file].extend(lines);
+	}
+
+	/// No more lines will be pushed to the file
+	pub fn finish(&mut self, file: usize) {
+		self.finished[file] = true;
+	}
+
+	/// The file which must be read before the next line can be merged
+	pub fn needs_input(&self) -> Option<usize> {
+		(0..self.files.len()).find(|&i| !self.finished[i] && self.files[i].is_empty())
+	}
+
+	/// The oldest buffered line, `None` when all the files are finished or `needs_input`
+	pub fn pop(&mut self) -> Option<LogLine> {
+		if self.needs_input().is_some() {
+			return None;
+		}
+		let (file, _) = self.files.iter().enumerate()
+			.filter_map(|(i, f)| f.front().map(|l| (i, l.time)))
+			.min_by_key(|&(i, time)| (time, i))?;
+		self.files[file].pop_front()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Line at `sec`, `id` is stored in the size to tell the lines apart
+	fn line(sec: i64, id: u64) -> LogLine {
+		LogLine {
+			time: DateTime::from_timestamp(sec, 0).unwrap(), size: id,
+			ip: 0, http_version: 0, method: 0, domain: 0, path: 0, status_code: 200, referer: 0, user_agent: 0, content_type: 0, compression_type: 0, remote_user: 0, session_id: 0, country: 0,
+		}
+	}
+
+	fn times(lines: &[LogLine]) -> Vec<(i64, u64)> {
+		lines.iter().map(|l| (l.time.timestamp(), l.size)).collect()
+	}
+
+	#[test]
+	fn sorts_lines_in_the_window() {
+		let mut buffer = ReorderBuffer::new(60);
+		let mut out = vec![];
+		for (i, sec) in [10, 5, 12, 5].into_iter().enumerate() {
+			buffer.push(line(sec, i as u64), &mut out);
+		}
+		assert!(out.is_empty());
+		buffer.finish(&mut out);
+		// equal times keep the input order
+		assert_eq!(times(&out), [(5, 1), (5, 3), (10, 0), (12, 2)]);
+		assert_eq!(buffer.late_lines, 0);
+	}
+
+	#[test]
+	fn writes_lines_older_than_the_window() {
+		let mut buffer = ReorderBuffer::new(10);
+		let mut out = vec![];
+		buffer.push(line(0, 0), &mut out);
+		buffer.push(line(5, 1), &mut out);
+		assert!(out.is_empty());
+		buffer.push(line(30, 2), &mut out);
+		assert_eq!(times(&out), [(0, 0), (5, 1)]);
+		buffer.finish(&mut out);
+		assert_eq!(times(&out), [(0, 0), (5, 1), (30, 2)]);
+	}
+
+	#[test]
+	fn clamps_late_lines_to_the_written_time() {
+		let mut buffer = ReorderBuffer::new(10);
+		let mut out = vec![];
+		for (i, sec) in [0, 30, 25, 100, 20].into_iter().enumerate() {
+			buffer.push(line(sec, i as u64), &mut out);
+		}
+		buffer.finish(&mut out);
+		assert_eq!(times(&out), [(0, 0), (25, 2), (30, 1), (30, 4), (100, 3)]);
+		assert_eq!(buffer.late_lines, 1);
+	}
+
+	#[test]
+	fn merges_files_lazily() {
+		let mut merge = TimeMerge::new(2);
+		assert_eq!(merge.needs_input(), Some(0));
+		assert!(merge.pop().is_none());
+		merge.push(0, [line(1, 0), line(3, 1)]);
+		assert_eq!(merge.needs_input(), Some(1));
+		merge.push(1, [line(2, 10), line(3, 11)]);
+		assert_eq!(merge.needs_input(), None);
+
+		let mut out = vec![];
+		out.extend(std::iter::from_fn(|| merge.pop()));
+		// equal times are taken from the earlier file first, the second file waits for more lines of the first one
+		assert_eq!(times(&out), [(1, 0), (2, 10), (3, 1)]);
+		assert_eq!(merge.needs_input(), Some(0));
+
+		merge.finish(0);
+		out.extend(std::iter::from_fn(|| merge.pop()));
+		assert_eq!(merge.needs_input(), Some(1));
+		merge.finish(1);
+		assert_eq!(merge.needs_input(), None);
+		assert!(merge.pop().is_none());
+		assert_eq!(times(&out), [(1, 0), (2, 10), (3, 1), (3, 11)]);
+	}
+}
@@ -0,0 +1,191 @@
+//! Navigation trees: with `LoadOptions::referer_tree`, each page of a session is attached to the page
+//! in its referer, so the visits in more tabs or after going back form branches instead of one sequence.
+use crate::{parser::GlobalTable, session_analyzer::Session};
+
+/// Maps the referer URLs to the ids of the paths on this site
+#[derive(Default)]
+pub struct RefererPaths {
+	/// for each referer id
+	paths: Vec<RefererPath>,
+}
+
+#[derive(Clone, Default)]
+enum RefererPath {
+	/// not a URL of a page (`-`)
+	#[default]
+	Invalid,
+	/// host and path of a page which is not in the path table (yet)
+	Unknown(String, String),
+	/// host and path id
+	Page(String, u32),
+}
+
+impl RefererPaths {
+	/// Path id of the page in the referer, `None` when the referer is on another host than the request `domain`.
+	/// Logs without the host (empty `domain`) accept the referers on any host.
+	pub fn path_id(&mut self, referer: u32, domain: u32, table: &GlobalTable) -> Option<u32> {
+		if referer as usize >= self.paths.len() {
+			// the table grows while the logs are read, only the referers added since the last time are parsed
+			let new: Vec<(usize, RefererPath)> = table.referer.iter()
+				.filter(|&(_, &id)| id as usize >= self.paths.len())
+				.map(|(r, &id)| (id as usize, parse_referer(r, table)))
+				.collect();
+			let len = new.iter().map(|(id, _)| id + 1).chain([referer as usize + 1]).max().unwrap();
+			self.paths.resize(len, RefererPath::Invalid);
+			for (id, path) in new {
+				self.paths[id] = path;
+			}
+		}
+		let entry = &mut self.paths[referer as usize];
+		if let RefererPath::Unknown(host, path) = entry {
+			// the page may have been visited since
+			if let Some(id) = find_path(path, table) {
+				*entry = RefererPath::Page(std::mem::take(host), id);
+			}
+		}
+		let RefererPath::Page(host, path) = entry else {
+			return None;
+		};
+		let same_site = host.is_empty() || [host.as_str(), ""].iter().any(|h| table.domain.get(*h) == Some(&domain));
+		same_site.then_some(*path)
+	}
+}
+
+/// `https://example.com/a/b/?x=1#top` -> `example.com` and the id of `/a/b?x=1` or `/a/b`, the same normalization as `GlobalTable::add_path`.
+/// Relative referers get an empty host.
+fn parse_referer(referer: &str, table: &GlobalTable) -> RefererPath {
+	let (host, path) = match referer.find("://") {
+		Some(scheme_end) => {
+			let rest = &referer[scheme_end + 3..];
+			let Some(path_start) = rest.find('/') else {
+				return RefererPath::Invalid;
+			};
+			(rest[..path_start].to_ascii_lowercase(), &rest[path_start..])
+		},
+		None if referer.starts_with('/') => (String::new(), referer),
+		None => return RefererPath::Invalid,
+	};
+	let path = path.split('#').next().unwrap_or("");
+	match find_path(path, table) {
+		Some(id) => RefererPath::Page(host, id),
+		None => RefererPath::Unknown(host, path.to_owned()),
+	}
+}
+
+fn find_path(path: &str, table: &GlobalTable) -> Option<u32> {
+	let without_query = path.split('?').next().unwrap_or("");
+	[path, without_query].into_iter()
+		.map(|p| p.strip_suffix('/').unwrap_or(p))
+		.find_map(|p| table.path.get(p).copied())
+}
+
+/// Splits the navigation tree into the sequences from the first page to each page where the visitor left.
+/// Sessions without a tree are returned unchanged.
+pub fn flatten_tree(s: &Session) -> Vec<Session> {
+	if s.parents.is_empty() {
+		return vec![ s.clone() ];
+	}
+	let mut has_child = vec![false; s.actions.len()];
+	for p in s.parents.iter().flatten() {
+		has_child[*p as usize] = true;
+	}
+
+	(0..s.actions.len()).filter(|&i| !has_child[i]).map(|leaf| {
+		let mut branch = vec![leaf];
+		while let Some(parent) = s.parents[*branch.last().unwrap()] {
+			branch.push(parent as usize);
+		}
+		branch.reverse();
+
+		let mut b = s.clone();
+		b.actions = branch.iter().map(|&i| s.actions[i]).collect();
+		b.access_times = branch.iter().map(|&i| s.access_times[i]).collect();
+		b.details = branch.iter().map(|&i| s.details[i]).collect();
+		b.parents = vec![];
+		b
+	}).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use chrono::DateTime;
+
+	use super::*;
+	use crate::{parser::get_or_add, session_analyzer::ActionDetails, session_key::Identity, stats::{calc_graph, GraphMode, StatsOptions}};
+
+	/// Session of the `(path, parent)` pages a second apart
+	fn tree(table: &mut GlobalTable, pages: &[(&str, Option<u32>)]) -> Session {
+		let time = DateTime::from_timestamp(0, 0).unwrap();
+		let details = ActionDetails { status_code: 200, method: 0, domain: 0, referer: 0, content_type: 0, bytes: 0 };
+		Session {
+			identity: Identity::IpUserAgent { ip: 0, user_agent: 0 },
+			ip: 0, user_agent: 0, referer: 0, country: 0,
+			start_time: time, end_time: time, last_request_time: time,
+			actions: pages.iter().map(|&(p, _)| table.add_path(p)).collect(),
+			access_times: (0..pages.len() as u32).collect(),
+			details: vec![details; pages.len()],
+			parents: pages.iter().map(|&(_, parent)| parent).collect(),
+			total_requests: pages.len() as u32,
+			total_bytes: 0,
+		}
+	}
+
+	fn paths<'a>(s: &Session, table: &'a GlobalTable) -> Vec<&'a str> {
+		s.actions.iter().map(|&p| table.path_list[p as usize].as_str()).collect()
+	}
+
+	#[test]
+	fn resolves_the_referers_on_the_request_host() {
+		let mut table = GlobalTable::new();
+		let a = table.add_path("/a");
+		let example = get_or_add(&mut table.domain, "example.com");
+		let other = get_or_add(&mut table.domain, "other.com");
+		let absolute = get_or_add(&mut table.referer, "https://Example.com/a/?x=1#top");
+		let relative = get_or_add(&mut table.referer, "/a");
+		let none = get_or_add(&mut table.referer, "-");
+		let unknown = get_or_add(&mut table.referer, "https://example.com/b");
+
+		let mut referers = RefererPaths::default();
+		assert_eq!(referers.path_id(absolute, example, &table), Some(a));
+		assert_eq!(referers.path_id(absolute, other, &table), None);
+		assert_eq!(referers.path_id(relative, other, &table), Some(a));
+		assert_eq!(referers.path_id(none, example, &table), None);
+		assert_eq!(referers.path_id(unknown, example, &table), None);
+		// the page is visited after the referer was seen
+		let b = table.add_path("/b");
+		assert_eq!(referers.path_id(unknown, example, &table), Some(b));
+		// logs without the host accept any one
+		let no_host = get_or_add(&mut table.domain, "");
+		assert_eq!(referers.path_id(absolute, no_host, &table), Some(a));
+	}
+
+	#[test]
+	fn splits_the_tree_into_branches() {
+		let mut table = GlobalTable::new();
+		let s = tree(&mut table, &[("/", None), ("/a", Some(0)), ("/b", Some(0)), ("/c", Some(2)), ("/d", None)]);
+		let branches: Vec<_> = flatten_tree(&s).iter().map(|b| paths(b, &table)).collect();
+		assert_eq!(branches, [vec!["", "/a"], vec!["", "/b", "/c"], vec!["/d"]]);
+		assert_eq!(flatten_tree(&s)[1].access_times, [0, 2, 3]);
+
+		let sequence = Session { parents: vec![], ..s };
+		assert_eq!(flatten_tree(&sequence).len(), 1);
+	}
+
+	#[test]
+	fn counts_a_tree_once_in_the_graph() {
+		let mut table = GlobalTable::new();
+		let sessions = [tree(&mut table, &[("/h", None), ("/a", Some(0)), ("/b", Some(0)), ("/c", Some(2))])];
+		let opt = StatsOptions { rewrite_rules: vec![], ..StatsOptions::new(0, 1, 10) };
+		let g = calc_graph(&sessions, &table, 3, &opt, "", "", GraphMode::Forward).unwrap();
+		let node = |layer: usize, path: &str| g.layers[layer].nodes.iter().find(|n| n.path == path).unwrap();
+		let h = node(0, "/h");
+		assert_eq!((h.session_count, h.entry_count), (1, 1));
+		assert_eq!(h.transfer_count.values().sum::<u32>(), 2);
+		assert_eq!(h.exit_count, 0);
+		// each branch ends where the visitor left
+		assert_eq!((node(1, "/a").session_count, node(1, "/a").exit_count), (1, 1));
+		assert_eq!(node(1, "/b").session_count, 1);
+		assert_eq!((node(2, "/c").session_count, node(2, "/c").exit_count), (1, 1));
+	}
+}
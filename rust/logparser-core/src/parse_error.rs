@@ -0,0 +1,187 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Serialize, Deserialize};
+
+use crate::parser::Field;
+
+/// Number of the failed lines kept in `ParseStats::samples`
+pub const MAX_ERROR_SAMPLES: usize = 5;
+/// Longer lines are truncated in the samples
+const MAX_SAMPLE_LENGTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseErrorKind {
+	/// the line ended before all fields were read
+	UnexpectedEnd,
+	MissingQuote,
+	InvalidNumber,
+	InvalidDate,
+	/// `"GET /path HTTP/1.1"` could not be split
+	MalformedRequest,
+	/// a required field is empty or not in the regex match
+	MissingField,
+	/// the regex of a custom format did not match
+	NoMatch,
+	InvalidJson,
+}
+
+impl ParseErrorKind {
+	pub fn name(self) -> &'static str {
+		match self {
+			ParseErrorKind::UnexpectedEnd => "unexpected end of line",
+			ParseErrorKind::MissingQuote => "missing closing quote",
+			ParseErrorKind::InvalidNumber => "invalid number",
+			ParseErrorKind::InvalidDate => "invalid date",
+			ParseErrorKind::MalformedRequest => "malformed request line",
+			ParseErrorKind::MissingField => "missing field",
+			ParseErrorKind::NoMatch => "pattern did not match",
+			ParseErrorKind::InvalidJson => "invalid JSON",
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseError {
+	pub kind: ParseErrorKind,
+	/// the field which could not be read, `None` for the fields we don't use or when it's not known
+	pub field: Option<Field>,
+	/// byte offset in the line, `None` when it's not known (the regex of a custom format did not say where it failed)
+	pub offset: Option<usize>,
+	pub reason: String,
+}
+
+impl ParseError {
+	pub fn new(kind: ParseErrorKind, field: Option<Field>, offset: usize, reason: impl Into<String>) -> ParseError {
+		ParseError { kind, field, offset: Some(offset), reason: reason.into() }
+	}
+
+	pub fn without_offset(kind: ParseErrorKind, field: Option<Field>, reason: impl Into<String>) -> ParseError {
+		ParseError { kind, field, offset: None, reason: reason.into() }
+	}
+
+	/// Error at the start of `at`, which must be a slice of `line`
+	pub fn at(kind: ParseErrorKind, field: Option<Field>, line: &[u8], at: &[u8], reason: impl Into<String>) -> ParseError {
+		let offset = (at.as_ptr() as usize).saturating_sub(line.as_ptr() as usize).min(line.len());
+		ParseError::new(kind, field, offset, reason)
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.kind.name())?;
+		if let Some(field) = self.field {
+			write!(f, " in {}", field.name())?;
+		}
+		if let Some(offset) = self.offset {
+			write!(f, " at byte {}", offset)?;
+		}
+		if !self.reason.is_empty() {
+			write!(f, ": {}", self.reason)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorSample {
+	pub line: String,
+	pub error: ParseError,
+}
+
+/// How much of the input was actually used
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParseStats {
+	pub total_lines: u64,
+	pub parsed_lines: u64,
+	/// empty lines
+	pub skipped_lines: u64,
+	pub error_count: u64,
+	pub errors_by_kind: BTreeMap<ParseErrorKind, u64>,
+	/// first few lines which could not be parsed
+	pub samples: Vec<ErrorSample>,
+}
+
+impl ParseStats {
+	pub fn add_error(&mut self, line: &str, error: ParseError) {
+		self.error_count += 1;
+		*self.errors_by_kind.entry(error.kind).or_insert(0) += 1;
+		if self.samples.len() < MAX_ERROR_SAMPLES {
+			let mut end = line.len().min(MAX_SAMPLE_LENGTH);
+			while !line.is_char_boundary(end) {
+				end -= 1;
+			}
+			self.samples.push(ErrorSample { line: line[..end].to_owned(), error });
+		}
+	}
+
+	/// e.g. `invalid date: 10, missing closing quote: 2`
+	pub fn describe_errors(&self) -> String {
+		self.errors_by_kind.iter().map(|(k, c)| format!("{}: {}", k.name(), c)).collect::<Vec<_>>().join(", ")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::{executor::block_on, stream};
+
+	use super::*;
+	use crate::{load_sessions, parser::{FormatSpec, GlobalTable}, session_analyzer::Sessionizer, LoadOptions, LoadResult};
+
+	const LINE: &str = r#"1.2.3.4 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.0" 200 1 "-" "Mozilla/5.0""#;
+
+	/// Loads the combined log lines as one file
+	fn load(lines: &[&str], max_errors: Option<u64>) -> Result<LoadResult, String> {
+		let options = LoadOptions {
+			format: FormatSpec::Preset("combined".to_owned()),
+			ignore_query_string: true,
+			max_age: 3600,
+			max_errors,
+			timezone: None,
+			reorder_window: 0,
+			bot_filter: Default::default(),
+			session_key: Default::default(),
+			referer_tree: false,
+			classifier: Default::default(),
+			keep_open_sessions: false,
+		};
+		let data = lines.iter().map(|l| format!("{}\n", l)).collect::<String>().into_bytes();
+		block_on(load_sessions(vec![stream::iter([Ok(data)])], &options, &mut GlobalTable::new(), &mut Sessionizer::default()))
+	}
+
+	#[test]
+	fn caps_the_samples() {
+		let mut stats = ParseStats::default();
+		for i in 0..MAX_ERROR_SAMPLES + 3 {
+			let kind = if i % 2 == 0 { ParseErrorKind::InvalidDate } else { ParseErrorKind::MissingQuote };
+			stats.add_error(&format!("line {}", i), ParseError::new(kind, None, 0, ""));
+		}
+		assert_eq!(stats.error_count, MAX_ERROR_SAMPLES as u64 + 3);
+		assert_eq!(stats.samples.len(), MAX_ERROR_SAMPLES);
+		assert_eq!(stats.samples[0].line, "line 0");
+		assert_eq!(stats.describe_errors(), "missing closing quote: 4, invalid date: 4");
+		// long lines are cut at a character boundary
+		let mut stats = ParseStats::default();
+		stats.add_error(&"é".repeat(MAX_SAMPLE_LENGTH), ParseError::new(ParseErrorKind::NoMatch, None, 0, ""));
+		assert_eq!(stats.samples[0].line.len(), MAX_SAMPLE_LENGTH);
+	}
+
+	#[test]
+	fn fails_after_max_errors() {
+		let bad = ["garbage", "", "1.2.3.4 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.0\" 2x0 1 \"-\" \"ua\""];
+		let lines: Vec<&str> = [LINE].iter().chain(&bad).chain(&bad).copied().collect();
+		let e = load(&lines, Some(3)).err().unwrap();
+		assert!(e.starts_with("More than 3 lines could not be parsed"), "{}", e);
+		assert!(e.ends_with(": garbage"), "{}", e);
+
+		let r = load(&lines, Some(4)).ok().unwrap();
+		let stats = &r.summary.lines;
+		assert_eq!((stats.total_lines, stats.parsed_lines, stats.skipped_lines, stats.error_count), (7, 1, 2, 4));
+		assert_eq!(stats.errors_by_kind.values().sum::<u64>(), 4);
+		assert_eq!(stats.errors_by_kind[&ParseErrorKind::InvalidNumber], 2);
+		// without the limit, the errors are only counted
+		assert_eq!(load(&lines, None).ok().unwrap().summary.lines.error_count, 4);
+	}
+}
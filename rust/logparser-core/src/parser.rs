@@ -0,0 +1,704 @@
+use std::{collections::{HashMap, BTreeMap}, str::FromStr};
+use regex::{Regex};
+use chrono::prelude::*;
+use chrono::LocalResult;
+use chrono_tz::Tz;
+use serde::{Serialize, Deserialize};
+
+use crate::presets;
+use crate::parse_error::{ParseError, ParseErrorKind};
+
+// 2021-05-01 02:16:15 "1.1.1.1" "HTTP/1.0" GET ksp.mff.cuni.cz "/img/home.png" 304 0 0 "https://ksp.mff.cuni.cz/css/@a4d399ff6702838c/ksp.css" "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/90.0.4430.93 Safari/537.36" "-" 61647 "-" "-"
+
+// KSP log order (`parse_line_handwritten1`):
+// 1. datetime
+// 2. ip
+// 3. http version
+// 4. method
+// 5. domain
+// 6. path
+// 7. status code
+// 8. size
+// 9. some number, IDK
+// 10. referer
+// 11. user agent
+// 12. some string, IDK
+// 13. some number, IDK
+// 14. content type
+// 15. compression type
+
+/// Fields of the `LogLine` which can be read from the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+	Time,
+	Ip,
+	HttpVersion,
+	Method,
+	Domain,
+	Path,
+	StatusCode,
+	Size,
+	Referer,
+	UserAgent,
+	ContentType,
+	CompressionType,
+	/// authenticated user (`%u` in Apache)
+	RemoteUser,
+	/// value of a session cookie or another client identifier
+	SessionId,
+	/// country code of the client, e.g. from a GeoIP module or the `CF-IPCountry` header
+	Country,
+}
+
+impl Field {
+	pub const ALL: [Field; 15] = [
+		Field::Time, Field::Ip, Field::HttpVersion, Field::Method, Field::Domain, Field::Path,
+		Field::StatusCode, Field::Size, Field::Referer, Field::UserAgent, Field::ContentType, Field::CompressionType,
+		Field::RemoteUser, Field::SessionId, Field::Country
+	];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Field::Time => "time",
+			Field::Ip => "ip",
+			Field::HttpVersion => "http_version",
+			Field::Method => "method",
+			Field::Domain => "domain",
+			Field::Path => "path",
+			Field::StatusCode => "status_code",
+			Field::Size => "size",
+			Field::Referer => "referer",
+			Field::UserAgent => "user_agent",
+			Field::ContentType => "content_type",
+			Field::CompressionType => "compression_type",
+			Field::RemoteUser => "remote_user",
+			Field::SessionId => "session_id",
+			Field::Country => "country",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Field> {
+		Field::ALL.into_iter().find(|f| f.name() == name)
+	}
+}
+
+/// Where the value of a field is in the regex match
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldSource {
+	/// index of the capture group (1-based, like in the regex)
+	Index(usize),
+	/// name of the capture group, `(?P<name>...)`
+	Group(String),
+}
+
+/// Declarative description of a log layout.
+///
+/// Capture groups named like a field (`(?P<user_agent>"[^"]*")`) are used automatically,
+/// `fields` maps the others. Fields which are not in the log get a default value
+/// (empty string, status 200, size 0), only `time` and `path` are required.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogFormat {
+	pub pattern: String,
+	pub datetime_format: String,
+	#[serde(default)]
+	pub fields: BTreeMap<Field, FieldSource>,
+	/// fields whose capture group may not participate in the match (default value is used then)
+	#[serde(default)]
+	pub optional: Vec<Field>,
+}
+
+/// Log format selected by the user, either a name of built-in format or a custom one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FormatSpec {
+	Preset(String),
+	Custom(LogFormat),
+}
+
+/// Names of the built-in formats, more specific formats go first (combined is a superset of common)
+pub const PRESETS: [&str; 5] = ["ksp", "combined", "common", "caddy", "traefik"];
+/// Format name which makes `load_sessions` detect the format from the first lines of the log
+pub const AUTO_FORMAT: &str = "auto";
+
+struct RegexFormat {
+	regex: Regex,
+	/// (capture index, is optional) for each `Field`
+	fields: [Option<(usize, bool)>; Field::ALL.len()],
+}
+
+enum LineFormat {
+	/// `parse_line_handwritten1`
+	Ksp,
+	/// NCSA common log format
+	Common,
+	/// Apache/Nginx combined log format
+	Combined,
+	Caddy,
+	Traefik,
+	Regex(Box<RegexFormat>),
+}
+
+/// How the time zone of the date in the log is determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateTimeKind {
+	/// local time in `LogParser::timezone`
+	Naive,
+	/// the date contains the UTC offset (`%z`)
+	WithOffset,
+	/// unix timestamp (`%s`)
+	Timestamp,
+}
+
+impl DateTimeKind {
+	fn of(datetime_format: &str) -> DateTimeKind {
+		if ["%z", "%:z", "%::z", "%:::z", "%#z", "%+"].iter().any(|x| datetime_format.contains(x)) {
+			DateTimeKind::WithOffset
+		} else if datetime_format.contains("%s") {
+			DateTimeKind::Timestamp
+		} else {
+			DateTimeKind::Naive
+		}
+	}
+}
+
+pub struct LogParser {
+	format: LineFormat,
+	datetime_format: String,
+	datetime_kind: DateTimeKind,
+	/// zone of the dates without UTC offset
+	timezone: Tz,
+	ignore_query_string: bool
+}
+
+impl LogParser {
+	pub fn new(spec: &FormatSpec, ignore_query_string: bool) -> Result<LogParser, String> {
+		let preset = |format: LineFormat, datetime_format: &str| Ok(LogParser {
+			format,
+			datetime_format: datetime_format.to_owned(),
+			datetime_kind: DateTimeKind::of(datetime_format),
+			timezone: Tz::UTC,
+			ignore_query_string
+		});
+		match spec {
+			FormatSpec::Preset(name) => match name.as_str() {
+				"ksp" => preset(LineFormat::Ksp, "%Y-%m-%d %H:%M:%S"),
+				"common" => preset(LineFormat::Common, "%d/%b/%Y:%H:%M:%S %z"),
+				"combined" => preset(LineFormat::Combined, "%d/%b/%Y:%H:%M:%S %z"),
+				"caddy" => preset(LineFormat::Caddy, ""),
+				"traefik" => preset(LineFormat::Traefik, ""),
+				AUTO_FORMAT => Err("The log format must be detected first, see detect::detect_format".to_owned()),
+				_ => Err(format!("Unknown log format {}, known formats are {:?}", name, PRESETS)),
+			},
+			FormatSpec::Custom(f) => LogParser::compile(f, ignore_query_string),
+		}
+	}
+
+	pub fn compile(format: &LogFormat, ignore_query_string: bool) -> Result<LogParser, String> {
+		log::debug!("pattern = {}", format.pattern);
+		let regex = Regex::new(&format.pattern).map_err(|e| e.to_string())?;
+		let mut fields = [None; Field::ALL.len()];
+
+		for (i, name) in regex.capture_names().enumerate() {
+			if let Some(f) = name.and_then(Field::from_name) {
+				fields[f as usize] = Some((i, false));
+			}
+		}
+		for (&f, source) in format.fields.iter() {
+			let idx = match source {
+				FieldSource::Index(i) if *i > 0 && *i < regex.captures_len() => *i,
+				FieldSource::Index(i) => return Err(format!("Field {}: capture group {} does not exist, the pattern has {} groups", f.name(), i, regex.captures_len() - 1)),
+				FieldSource::Group(g) =>
+					regex.capture_names().position(|n| n == Some(g.as_str()))
+						.ok_or_else(|| format!("Field {}: capture group {} does not exist", f.name(), g))?,
+			};
+			fields[f as usize] = Some((idx, false));
+		}
+		for &f in format.optional.iter() {
+			if let Some((_, optional)) = &mut fields[f as usize] {
+				*optional = true;
+			}
+		}
+
+		for f in [Field::Time, Field::Path] {
+			if fields[f as usize].is_none() {
+				return Err(format!("Field {} is required", f.name()));
+			}
+		}
+
+		Ok(LogParser {
+			format: LineFormat::Regex(Box::new(RegexFormat { regex, fields })),
+			datetime_format: format.datetime_format.clone(),
+			datetime_kind: DateTimeKind::of(&format.datetime_format),
+			timezone: Tz::UTC,
+			ignore_query_string
+		})
+	}
+
+	/// Sets the zone of the logs which don't include the UTC offset, UTC is the default
+	pub fn with_timezone(mut self, timezone: Tz) -> LogParser {
+		self.timezone = timezone;
+		self
+	}
+
+	/// Parses the date using `datetime_format`
+	pub(crate) fn parse_time(&self, s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+		Ok(match self.datetime_kind {
+			DateTimeKind::Naive => local_to_utc(&self.timezone, NaiveDateTime::parse_from_str(s, &self.datetime_format)?),
+			DateTimeKind::WithOffset => DateTime::parse_from_str(s, &self.datetime_format)?.to_utc(),
+			DateTimeKind::Timestamp => NaiveDateTime::parse_from_str(s, &self.datetime_format)?.and_utc(),
+		})
+	}
+
+	pub fn parse_line(&self, table: &mut GlobalTable, line: &str) -> Result<LogLine, ParseError> {
+		match &self.format {
+			LineFormat::Ksp => parse_line_handwritten1(self, table, line),
+			LineFormat::Common => presets::parse_line_clf(self, table, line, false),
+			LineFormat::Combined => presets::parse_line_clf(self, table, line, true),
+			LineFormat::Caddy => presets::parse_line_caddy(self, table, line),
+			LineFormat::Traefik => presets::parse_line_traefik(self, table, line),
+			LineFormat::Regex(f) => parse_line(self, f, table, line),
+		}
+	}
+
+	pub(crate) fn strip_query_string<'a>(&self, s: &'a str) -> &'a str {
+		if self.ignore_query_string {
+			if let Some(idx) = s.find('?') {
+				&s[0..idx]
+			} else {
+				s
+			}
+		} else {
+			s
+		}
+	}
+	pub(crate) fn strip_query_string_u8<'a>(&self, s: &'a[u8]) -> &'a[u8] {
+		if self.ignore_query_string {
+			if let Some(idx) = findidx(s, b'?') {
+				&s[0..idx]
+			} else {
+				s
+			}
+		} else {
+			s
+		}
+	}
+}
+
+/// `Europe/Prague` -> `Tz`
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+	name.parse().map_err(|_| format!("Unknown timezone {}, expected IANA name like Europe/Prague or UTC", name))
+}
+
+/// Converts the local time in `timezone` to UTC
+pub fn local_to_utc(timezone: &Tz, time: NaiveDateTime) -> DateTime<Utc> {
+	match timezone.from_local_datetime(&time) {
+		LocalResult::Single(t) => t.to_utc(),
+		// the hour repeated when the clock goes back, we can't tell which one it was
+		LocalResult::Ambiguous(t, _) => t.to_utc(),
+		// the hour skipped when the clock goes forward, the server must have used the other offset
+		LocalResult::None => (time - timezone.offset_from_utc_datetime(&time).fix()).and_utc(),
+	}
+}
+
+pub struct GlobalTable {
+	pub ip: HashMap<String, u32>,
+	pub http_version: HashMap<String, u32>,
+	pub method: HashMap<String, u32>,
+	pub domain: HashMap<String, u32>,
+	pub path: HashMap<String, u32>,
+	pub path_list: Vec<String>,
+	pub referer: HashMap<String, u32>,
+	pub user_agent: HashMap<String, u32>,
+	pub content_type: HashMap<String, u32>,
+	pub compression_type: HashMap<String, u32>,
+	pub remote_user: HashMap<String, u32>,
+	pub session_id: HashMap<String, u32>,
+	pub country: HashMap<String, u32>,
+}
+
+impl Default for GlobalTable {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl GlobalTable {
+	pub fn new() -> GlobalTable {
+		let content_type =
+			vec![
+				"".to_owned(),
+				"text/html".to_owned(),
+				"text/css".to_owned(),
+				"text/javascript".to_owned(),
+				"application/javascript".to_owned(),
+				"text/json".to_owned(),
+				"application/json".to_owned(),
+				"image/png".to_owned(),
+				"image/jpeg".to_owned(),
+				"image/gif".to_owned(),
+				"image/svg+xml".to_owned(),
+			].into_iter().enumerate().map(|(a, b)| (b, a as u32)).collect();
+		let paths = [
+			"/",
+			"/index.html",
+			"css",
+			"js",
+			"img",
+			"rest",
+			"api",
+			"admin"
+		];
+		GlobalTable {
+			ip: HashMap::new(),
+			http_version: HashMap::new(),
+			method: HashMap::new(),
+			domain: HashMap::new(),
+			path: paths.iter().enumerate().map(|(i, &x)| (x.to_owned(), i as u32)).collect(),
+			path_list: paths.iter().map(|&x| x.to_owned()).collect(),
+			referer: HashMap::new(),
+			user_agent: HashMap::new(),
+			content_type,
+			compression_type: HashMap::new(),
+			remote_user: HashMap::new(),
+			session_id: HashMap::new(),
+			country: HashMap::new(),
+		}
+	}
+
+	pub fn add_path(&mut self, path: &str) -> u32 {
+		if path.as_bytes().last().cloned() == Some(b'/') {
+			return self.add_path(&path[0..path.len()-1]);
+		}
+
+		if let Some(&idx) = self.path.get(path) {
+			idx
+		} else {
+			// also add all subpaths
+
+			let last_slash = path.rfind('/').unwrap_or(0);
+			if last_slash > 0 {
+				self.add_path(&path[0..last_slash]);
+			}
+
+
+			let idx = self.path_list.len() as u32;
+			self.path_list.push(path.to_owned());
+			self.path.insert(path.to_string(), idx);
+			idx
+		}
+
+	}
+
+}
+
+pub struct LogLine {
+	pub time: DateTime<Utc>,
+	pub ip: u32,
+	pub http_version: u32,
+	pub method: u32,
+	pub domain: u32,
+	pub path: u32,
+	pub status_code: u32,
+	pub size: u64,
+	pub referer: u32,
+	pub user_agent: u32,
+	pub content_type: u32,
+	pub compression_type: u32,
+	pub remote_user: u32,
+	pub session_id: u32,
+	pub country: u32,
+}
+
+pub(crate) fn get_or_add(table: &mut HashMap<String, u32>, key: &str) -> u32 {
+	if let Some(&idx) = table.get(key) {
+		idx
+	} else {
+		let idx = table.len() as u32 + 1;
+		table.insert(key.to_string(), idx);
+		idx
+	}
+}
+
+fn parse_line(p: &LogParser, f: &RegexFormat, table: &mut GlobalTable, line: &str) -> Result<LogLine, ParseError> {
+	let captures = f.regex.captures(line).ok_or_else(|| ParseError::without_offset(ParseErrorKind::NoMatch, None, ""))?;
+	let c = |field: Field| -> Result<Option<regex::Match>, ParseError> {
+		match f.fields[field as usize] {
+			None => Ok(None),
+			Some((idx, optional)) => match captures.get(idx) {
+				Some(m) => Ok(Some(m)),
+				None if optional => Ok(None),
+				None => Err(ParseError::without_offset(ParseErrorKind::MissingField, Some(field), "")),
+			}
+		}
+	};
+	let s = |field: Field| -> Result<&str, ParseError> { Ok(c(field)?.map_or("", |m| m.as_str())) };
+	let time = c(Field::Time)?.ok_or_else(|| ParseError::without_offset(ParseErrorKind::MissingField, Some(Field::Time), ""))?;
+	let time = p.parse_time(time.as_str())
+		.map_err(|e| ParseError::new(ParseErrorKind::InvalidDate, Some(Field::Time), time.start(), e.to_string()))?;
+	let ip = get_or_add(&mut table.ip, s(Field::Ip)?);
+	let http_version = get_or_add(&mut table.http_version, s(Field::HttpVersion)?);
+	let method = get_or_add(&mut table.method, s(Field::Method)?);
+	let domain = get_or_add(&mut table.domain, s(Field::Domain)?);
+	let path = table.add_path(p.strip_query_string(s(Field::Path)?));
+	let status_code = c(Field::StatusCode)?.map_or(Ok(200), |m| parse_int(line.as_bytes(), m.as_str().as_bytes(), Field::StatusCode))?;
+	let size = c(Field::Size)?.map_or(Ok(0), |m| parse_int(line.as_bytes(), m.as_str().as_bytes(), Field::Size))?;
+	let referer = get_or_add(&mut table.referer, p.strip_query_string(s(Field::Referer)?));
+	let user_agent = get_or_add(&mut table.user_agent, s(Field::UserAgent)?);
+	let content_type = get_or_add(&mut table.content_type, s(Field::ContentType)?);
+	let compression_type = get_or_add(&mut table.compression_type, s(Field::CompressionType)?);
+	let remote_user = get_or_add(&mut table.remote_user, no_dash(s(Field::RemoteUser)?));
+	let session_id = get_or_add(&mut table.session_id, no_dash(s(Field::SessionId)?));
+	let country = get_or_add(&mut table.country, no_dash(s(Field::Country)?));
+	Ok(LogLine { time, ip, http_version, method, domain, path, status_code, size, referer, user_agent, content_type, compression_type, remote_user, session_id, country })
+}
+
+/// `-` is used for the missing values in most formats
+pub(crate) fn no_dash(s: &str) -> &str {
+	if s == "-" { "" } else { s }
+}
+
+pub(crate) fn skip_space(mut s: &[u8]) -> &[u8] {
+	unsafe {
+		while !s.is_empty() && *s.get_unchecked(0) == b' ' {
+			s = s.get_unchecked(1..);
+		}
+		s
+	}
+}
+
+pub(crate) fn findidx(s: &[u8], c: u8) -> Option<usize> {
+	unsafe {
+		for i in 0..s.len() {
+			if *s.get_unchecked(i) == c {
+				return Some(i);
+			}
+		}
+		None
+	}
+}
+
+fn read_date<'a>(line: &[u8], s: &'a [u8]) -> Result<(&'a [u8], &'a [u8]), ParseError> {
+	let err = || ParseError::at(ParseErrorKind::UnexpectedEnd, Some(Field::Time), line, s, "expected date and time");
+	let midws = findidx(s, b' ').ok_or_else(err)?;
+	let endws = findidx(&s[midws+1..], b' ').ok_or_else(err)?;
+	Ok((&s[0..midws+endws+1], skip_space(&s[midws+endws+2..])))
+}
+
+fn find_end_quote(s: &[u8]) -> Option<usize> {
+	unsafe {
+		for i in 1..s.len() {
+			if *s.get_unchecked(i) == b'"' {
+				if *s.get_unchecked(i-1) != b'\\' {
+					return Some(i);
+				}
+				else {
+					// count the \, if their number is odd, we can continue
+					let mut cnt = 1;
+					while i > cnt && *s.get_unchecked(i-cnt-1) == b'\\' {
+						cnt += 1;
+					}
+					if (cnt % 2) == 0 {
+						return Some(i);
+					}
+				}
+			}
+		}
+		None
+	}
+}
+
+/// Reads a space separated field, the value may be in quotes. `field` is only used for the error
+pub(crate) fn read_field<'a>(line: &[u8], s: &'a [u8], field: Option<Field>) -> Result<(&'a [u8], &'a [u8]), ParseError> {
+unsafe {
+	if s.is_empty() {
+		return Err(ParseError::at(ParseErrorKind::UnexpectedEnd, field, line, s, ""));
+	}
+	if s[0] == b'"' {
+		let idx = find_end_quote(s).ok_or_else(|| ParseError::at(ParseErrorKind::MissingQuote, field, line, s, ""))?;
+		return Ok((s.get_unchecked(1..idx), skip_space(s.get_unchecked(idx+1..))))
+	}
+	match findidx(s, b' ') {
+		Some(idx) => Ok((s.get_unchecked(0..idx), skip_space(s.get_unchecked(idx..)))),
+		None => Ok((s, &s[s.len()..]))
+	}
+}
+}
+
+/// `s` is the field value, a slice of `line`
+pub(crate) fn parse_int<T: FromStr>(line: &[u8], s: &[u8], field: Field) -> Result<T, ParseError> {
+	let str = unsafe { std::str::from_utf8_unchecked(s) };
+	str.parse().ok().ok_or_else(|| ParseError::at(ParseErrorKind::InvalidNumber, Some(field), line, s, format!("{} is not {}", str, std::any::type_name::<T>())))
+}
+
+pub fn parse_line_handwritten1(p: &LogParser, table: &mut GlobalTable, line: &str) -> Result<LogLine, ParseError> {
+	unsafe fn get_or_add(table: &mut HashMap<String, u32>, key: &[u8]) -> u32 {
+		self::get_or_add(table, std::str::from_utf8_unchecked(key))
+	}
+
+	unsafe {
+		let line = line.as_bytes();
+		let s = skip_space(line);
+
+		let (time, s) = read_date(line, s)?;
+		let (ip, s) = read_field(line, s, Some(Field::Ip))?;
+		let (http_version, s) = read_field(line, s, Some(Field::HttpVersion))?;
+		let (method, s) = read_field(line, s, Some(Field::Method))?;
+		let (domain, s) = read_field(line, s, Some(Field::Domain))?;
+		let (path, s) = read_field(line, s, Some(Field::Path))?;
+		let (status_code, s) = read_field(line, s, Some(Field::StatusCode))?;
+		let (size, s) = read_field(line, s, Some(Field::Size))?;
+		let (_, s) = read_field(line, s, None)?;
+		let (referer, s) = read_field(line, s, Some(Field::Referer))?;
+		let (user_agent, s) = read_field(line, s, Some(Field::UserAgent))?;
+		let (_, s) = read_field(line, s, None)?;
+		let (_, s) = read_field(line, s, None)?;
+		let (content_type, s) = read_field(line, s, Some(Field::ContentType))?;
+		let (compression_type, _s) = read_field(line, s, Some(Field::CompressionType))?;
+
+		for (value, field) in [(domain, Field::Domain), (size, Field::Size), (status_code, Field::StatusCode)] {
+			if value.is_empty() {
+				return Err(ParseError::at(ParseErrorKind::MissingField, Some(field), line, value, ""));
+			}
+		}
+
+		// log!("time = {}, ip = {}, httpv = {}, method = {}", std::str::from_utf8_unchecked(time), std::str::from_utf8_unchecked(ip), std::str::from_utf8_unchecked(http_version), std::str::from_utf8_unchecked(method));
+
+		let time = p.parse_time(std::str::from_utf8_unchecked(time))
+			.map_err(|e| ParseError::at(ParseErrorKind::InvalidDate, Some(Field::Time), line, time, e.to_string()))?;
+
+		let ip = get_or_add(&mut table.ip, ip);
+		let http_version = get_or_add(&mut table.http_version, http_version);
+		let method = get_or_add(&mut table.method, method);
+		let domain = get_or_add(&mut table.domain, domain);
+		let path = table.add_path(std::str::from_utf8_unchecked(p.strip_query_string_u8(path)));
+		let status_code = parse_int(line, status_code, Field::StatusCode)?;
+		let size = parse_int(line, size, Field::Size)?;
+		let referer = get_or_add(&mut table.referer, p.strip_query_string_u8(referer));
+		let user_agent = get_or_add(&mut table.user_agent, user_agent);
+		let content_type = get_or_add(&mut table.content_type, content_type);
+		let compression_type = get_or_add(&mut table.compression_type, compression_type);
+		let remote_user = self::get_or_add(&mut table.remote_user, "");
+		let session_id = self::get_or_add(&mut table.session_id, "");
+		let country = self::get_or_add(&mut table.country, "");
+		Ok(LogLine { time, ip, http_version, method, domain, path, status_code, size, referer, user_agent, content_type, compression_type, remote_user, session_id, country })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const KSP_LINE: &str = r#"2021-05-01 02:16:15 "1.1.1.1" "HTTP/1.0" GET ksp.mff.cuni.cz "/img/home.png?v=2" 304 0 0 "https://ksp.mff.cuni.cz/css/ksp.css" "Mozilla/5.0 Chrome/90.0" "-" 61647 "image/png" "-""#;
+
+	fn name(mapping: &HashMap<String, u32>, id: u32) -> &str {
+		mapping.iter().find(|&(_, &v)| v == id).unwrap().0
+	}
+
+	fn custom(pattern: &str, datetime_format: &str) -> LogParser {
+		let format = LogFormat { pattern: pattern.to_owned(), datetime_format: datetime_format.to_owned(), fields: BTreeMap::new(), optional: vec![] };
+		LogParser::new(&FormatSpec::Custom(format), true).unwrap()
+	}
+
+	#[test]
+	fn parses_ksp_line() {
+		let mut table = GlobalTable::new();
+		let l = LogParser::new(&FormatSpec::Preset("ksp".to_owned()), true).unwrap().parse_line(&mut table, KSP_LINE).unwrap();
+		assert_eq!(l.time.to_rfc3339(), "2021-05-01T02:16:15+00:00");
+		assert_eq!(name(&table.ip, l.ip), "1.1.1.1");
+		assert_eq!(name(&table.http_version, l.http_version), "HTTP/1.0");
+		assert_eq!(name(&table.domain, l.domain), "ksp.mff.cuni.cz");
+		assert_eq!(table.path_list[l.path as usize], "/img/home.png");
+		assert_eq!((l.status_code, l.size), (304, 0));
+		assert_eq!(name(&table.referer, l.referer), "https://ksp.mff.cuni.cz/css/ksp.css");
+		assert_eq!(name(&table.user_agent, l.user_agent), "Mozilla/5.0 Chrome/90.0");
+		assert_eq!(name(&table.content_type, l.content_type), "image/png");
+	}
+
+	#[test]
+	fn keeps_the_query_string() {
+		let mut table = GlobalTable::new();
+		let l = LogParser::new(&FormatSpec::Preset("ksp".to_owned()), false).unwrap().parse_line(&mut table, KSP_LINE).unwrap();
+		assert_eq!(table.path_list[l.path as usize], "/img/home.png?v=2");
+		// the parent paths are added too
+		assert!(table.path.contains_key("/img"));
+	}
+
+	#[test]
+	fn reads_naive_dates_in_the_timezone() {
+		let mut table = GlobalTable::new();
+		let p = LogParser::new(&FormatSpec::Preset("ksp".to_owned()), true).unwrap().with_timezone(parse_timezone("Europe/Prague").unwrap());
+		assert_eq!(p.parse_line(&mut table, KSP_LINE).unwrap().time.to_rfc3339(), "2021-05-01T00:16:15+00:00");
+		assert!(parse_timezone("Mars/Olympus").is_err());
+	}
+
+	#[test]
+	fn reports_ksp_error_offsets() {
+		let p = LogParser::new(&FormatSpec::Preset("ksp".to_owned()), true).unwrap();
+		let mut table = GlobalTable::new();
+		let e = p.parse_line(&mut table, &KSP_LINE.replace("02:16:15", "02:16:xx")).err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidDate, Some(Field::Time), Some(0)));
+		let e = p.parse_line(&mut table, &KSP_LINE.replace(" 304 ", " 3o4 ")).err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidNumber, Some(Field::StatusCode), KSP_LINE.find("304")));
+		let e = p.parse_line(&mut table, "2021-05-01 02:16:15 \"1.1.1.1\"").err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::UnexpectedEnd, Some(Field::HttpVersion), Some(29)));
+	}
+
+	#[test]
+	fn parses_custom_format() {
+		let p = custom(r#"^(?P<ip>\S+) \[(?P<time>[^\]]+)\] (?P<method>\w+) (?P<path>\S+) (?P<status_code>\d+)(?: (?P<size>\d+))?$"#, "%Y-%m-%dT%H:%M:%S%z");
+		let mut table = GlobalTable::new();
+		let l = p.parse_line(&mut table, "1.2.3.4 [2024-03-31T01:02:03+0200] GET /a/b 404 10").unwrap();
+		assert_eq!(l.time.to_rfc3339(), "2024-03-30T23:02:03+00:00");
+		assert_eq!(name(&table.ip, l.ip), "1.2.3.4");
+		assert_eq!(table.path_list[l.path as usize], "/a/b");
+		assert_eq!((l.status_code, l.size), (404, 10));
+		// fields which are not in the log get the defaults
+		assert_eq!(name(&table.user_agent, l.user_agent), "");
+	}
+
+	#[test]
+	fn maps_fields_by_index_and_group() {
+		let mut fields = BTreeMap::new();
+		fields.insert(Field::Time, FieldSource::Index(1));
+		fields.insert(Field::Path, FieldSource::Group("url".to_owned()));
+		fields.insert(Field::Country, FieldSource::Index(3));
+		let format = LogFormat { pattern: r"^(\d+) (?P<url>\S+)(?: (\w+))?$".to_owned(), datetime_format: "%s".to_owned(), fields, optional: vec![Field::Country] };
+		let p = LogParser::new(&FormatSpec::Custom(format.clone()), true).unwrap();
+		let mut table = GlobalTable::new();
+		let l = p.parse_line(&mut table, "1700000000 /x CZ").unwrap();
+		assert_eq!(l.time.timestamp(), 1700000000);
+		assert_eq!(table.path_list[l.path as usize], "/x");
+		assert_eq!(name(&table.country, l.country), "CZ");
+		let l = p.parse_line(&mut table, "1700000000 /x").unwrap();
+		assert_eq!(name(&table.country, l.country), "");
+
+		let missing = LogFormat { optional: vec![], ..format };
+		let e = LogParser::new(&FormatSpec::Custom(missing), true).unwrap().parse_line(&mut table, "1700000000 /x").err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::MissingField, Some(Field::Country), None));
+	}
+
+	#[test]
+	fn rejects_invalid_custom_formats() {
+		let format = |pattern: &str, fields: BTreeMap<Field, FieldSource>| LogFormat { pattern: pattern.to_owned(), datetime_format: "%s".to_owned(), fields, optional: vec![] };
+		assert!(LogParser::new(&FormatSpec::Custom(format(r"(?P<time>\d+)", BTreeMap::new())), true).err().unwrap().contains("path is required"));
+		assert!(LogParser::new(&FormatSpec::Custom(format(r"(?P<time>\d+) (?P<path>\S+", BTreeMap::new())), true).is_err());
+		let fields = BTreeMap::from([(Field::Referer, FieldSource::Index(5))]);
+		assert!(LogParser::new(&FormatSpec::Custom(format(r"(?P<time>\d+) (?P<path>\S+)", fields)), true).err().unwrap().contains("capture group 5 does not exist"));
+		assert!(LogParser::new(&FormatSpec::Preset("apache2".to_owned()), true).is_err());
+	}
+
+	#[test]
+	fn reports_custom_format_errors() {
+		let p = custom(r#"^(?P<time>\S+ \S+) (?P<path>\S+) (?P<size>\S+)$"#, "%Y-%m-%d %H:%M:%S");
+		let mut table = GlobalTable::new();
+		// the regex does not tell where it failed
+		let e = p.parse_line(&mut table, "garbage").err().unwrap();
+		assert_eq!((e.kind, e.offset), (ParseErrorKind::NoMatch, None));
+		let e = p.parse_line(&mut table, "2021-05-01 25:00:00 /a 1").err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidDate, Some(Field::Time), Some(0)));
+		let e = p.parse_line(&mut table, "2021-05-01 02:00:00 /a big").err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidNumber, Some(Field::Size), Some(23)));
+	}
+}
@@ -0,0 +1,358 @@
+//! Parsers for the widespread access log formats, selectable by name (see `parser::PRESETS`)
+use std::borrow::Cow;
+
+use chrono::prelude::*;
+use serde::Deserialize;
+
+use crate::parser::*;
+use crate::parse_error::{ParseError, ParseErrorKind};
+
+// 127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 [en] (Win98; I ;Nav)"
+
+const MONTHS: [&[u8]; 12] = [b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec"];
+
+fn parse_digits(s: &[u8]) -> Option<u32> {
+	s.iter().try_fold(0u32, |acc, &c| if c.is_ascii_digit() { Some(acc * 10 + (c - b'0') as u32) } else { None })
+}
+
+/// Parses the `10/Oct/2000:13:55:36 -0700` date (without the brackets)
+pub fn parse_clf_date(s: &[u8]) -> Option<DateTime<FixedOffset>> {
+	if s.len() != 26 || s[2] != b'/' || s[6] != b'/' || s[11] != b':' || s[14] != b':' || s[17] != b':' || s[20] != b' ' {
+		return None;
+	}
+	let day = parse_digits(&s[0..2])?;
+	let month = MONTHS.iter().position(|&m| m == &s[3..6])? as u32 + 1;
+	let year = parse_digits(&s[7..11])?;
+	let hour = parse_digits(&s[12..14])?;
+	let minute = parse_digits(&s[15..17])?;
+	let second = parse_digits(&s[18..20])?;
+	let offset_sign = match s[21] { b'+' => 1, b'-' => -1, _ => return None };
+	let offset = (parse_digits(&s[22..24])? * 3600 + parse_digits(&s[24..26])? * 60) as i32;
+
+	let time = NaiveDate::from_ymd_opt(year as i32, month, day)?.and_hms_opt(hour, minute, second)?;
+	FixedOffset::east_opt(offset_sign * offset)?.from_local_datetime(&time).single()
+}
+
+/// NCSA common log format, and the combined format with referer and user agent (Apache and Nginx default)
+pub fn parse_line_clf(p: &LogParser, table: &mut GlobalTable, line: &str, combined: bool) -> Result<LogLine, ParseError> {
+	let line = line.as_bytes();
+	let s = skip_space(line);
+
+	let (ip, s) = read_field(line, s, Some(Field::Ip))?;
+	let (_ident, s) = read_field(line, s, None)?;
+	let (remote_user, s) = read_field(line, s, Some(Field::RemoteUser))?;
+	if s.first() != Some(&b'[') {
+		return Err(ParseError::at(ParseErrorKind::InvalidDate, Some(Field::Time), line, s, "expected [date]"));
+	}
+	let date_end = findidx(s, b']').ok_or_else(|| ParseError::at(ParseErrorKind::UnexpectedEnd, Some(Field::Time), line, s, "expected ]"))?;
+	let (time, s) = (&s[1..date_end], skip_space(&s[date_end + 1..]));
+	let (request, s) = read_field(line, s, Some(Field::Path))?;
+	let (status_code, s) = read_field(line, s, Some(Field::StatusCode))?;
+	let (size, s) = read_field(line, s, Some(Field::Size))?;
+	let (referer, user_agent) = if combined {
+		let (referer, s) = read_field(line, s, Some(Field::Referer))?;
+		let (user_agent, _s) = read_field(line, s, Some(Field::UserAgent))?;
+		(referer, user_agent)
+	} else {
+		(&b""[..], &b""[..])
+	};
+
+	// "GET /apache_pb.gif HTTP/1.0"
+	let mut request_parts = request.split(|&c| c == b' ').filter(|x| !x.is_empty());
+	let (method, path, http_version) = match (request_parts.next(), request_parts.next(), request_parts.next()) {
+		(Some(m), Some(p), v) => (m, p, v.unwrap_or(b"")),
+		_ => return Err(ParseError::at(ParseErrorKind::MalformedRequest, Some(Field::Path), line, request, String::from_utf8_lossy(request)))
+	};
+
+	let time = parse_clf_date(time)
+		.ok_or_else(|| ParseError::at(ParseErrorKind::InvalidDate, Some(Field::Time), line, time, String::from_utf8_lossy(time)))?
+		.to_utc();
+
+	unsafe {
+		let str = |x| std::str::from_utf8_unchecked(x);
+		let ip = get_or_add(&mut table.ip, str(ip));
+		let http_version = get_or_add(&mut table.http_version, str(http_version));
+		let method = get_or_add(&mut table.method, str(method));
+		let domain = get_or_add(&mut table.domain, "");
+		let path = table.add_path(str(p.strip_query_string_u8(path)));
+		let status_code = parse_int(line, status_code, Field::StatusCode)?;
+		let size = if size == b"-" { 0 } else { parse_int(line, size, Field::Size)? };
+		let referer = get_or_add(&mut table.referer, str(p.strip_query_string_u8(referer)));
+		let user_agent = get_or_add(&mut table.user_agent, str(user_agent));
+		let content_type = get_or_add(&mut table.content_type, "");
+		let compression_type = get_or_add(&mut table.compression_type, "");
+		let remote_user = get_or_add(&mut table.remote_user, no_dash(str(remote_user)));
+		let session_id = get_or_add(&mut table.session_id, "");
+		let country = get_or_add(&mut table.country, "");
+		Ok(LogLine { time, ip, http_version, method, domain, path, status_code, size, referer, user_agent, content_type, compression_type, remote_user, session_id, country })
+	}
+}
+
+fn json_error(e: serde_json::Error) -> ParseError {
+	// the lines are single-line JSON, so the column is the offset
+	ParseError::new(ParseErrorKind::InvalidJson, None, e.column().saturating_sub(1), e.to_string())
+}
+
+/// `text/html; charset=utf-8` -> `text/html`, the same as in the other formats
+fn mime_type(content_type: &str) -> &str {
+	content_type.split(';').next().unwrap_or("").trim()
+}
+
+fn first<'a>(values: &'a [Cow<'a, str>]) -> &'a str {
+	values.first().map_or("", |x| x.as_ref())
+}
+
+#[derive(Deserialize)]
+struct CaddyHeaders<'a> {
+	#[serde(rename = "User-Agent", default, borrow)]
+	user_agent: Vec<Cow<'a, str>>,
+	#[serde(rename = "Referer", default, borrow)]
+	referer: Vec<Cow<'a, str>>,
+	#[serde(rename = "Content-Type", default, borrow)]
+	content_type: Vec<Cow<'a, str>>,
+	#[serde(rename = "Content-Encoding", default, borrow)]
+	content_encoding: Vec<Cow<'a, str>>,
+}
+
+#[derive(Deserialize)]
+struct CaddyRequest<'a> {
+	#[serde(borrow)]
+	remote_ip: Cow<'a, str>,
+	/// newer Caddy versions, the address behind trusted proxies
+	#[serde(default, borrow)]
+	client_ip: Cow<'a, str>,
+	#[serde(borrow)]
+	proto: Cow<'a, str>,
+	#[serde(borrow)]
+	method: Cow<'a, str>,
+	#[serde(borrow)]
+	host: Cow<'a, str>,
+	#[serde(borrow)]
+	uri: Cow<'a, str>,
+	#[serde(borrow)]
+	headers: Option<CaddyHeaders<'a>>,
+}
+
+#[derive(Deserialize)]
+struct CaddyLine<'a> {
+	ts: f64,
+	/// set by the authentication handler
+	#[serde(default, borrow)]
+	user_id: Cow<'a, str>,
+	#[serde(borrow)]
+	request: CaddyRequest<'a>,
+	status: u32,
+	size: u64,
+	#[serde(borrow)]
+	resp_headers: Option<CaddyHeaders<'a>>,
+}
+
+// {"level":"info","ts":1646861401.52,"logger":"http.log.access","msg":"handled request","request":{"remote_ip":"127.0.0.1","remote_port":"41342","proto":"HTTP/2.0","method":"GET","host":"localhost","uri":"/","headers":{"User-Agent":["curl/7.82.0"]}},"duration":0.0009,"size":10900,"status":200,"resp_headers":{"Content-Type":["text/html; charset=utf-8"]}}
+
+/// JSON access log of the Caddy server
+pub fn parse_line_caddy(p: &LogParser, table: &mut GlobalTable, line: &str) -> Result<LogLine, ParseError> {
+	let l: CaddyLine = serde_json::from_str(line).map_err(json_error)?;
+	let r = &l.request;
+
+	let time = DateTime::from_timestamp_millis((l.ts * 1000.0) as i64)
+		.ok_or_else(|| ParseError::without_offset(ParseErrorKind::InvalidDate, Some(Field::Time), l.ts.to_string()))?;
+	let ip = if r.client_ip.is_empty() { &r.remote_ip } else { &r.client_ip };
+	let (user_agent, referer) = r.headers.as_ref().map_or(("", ""), |h| (first(&h.user_agent), first(&h.referer)));
+	let (content_type, compression_type) = l.resp_headers.as_ref().map_or(("", ""), |h| (mime_type(first(&h.content_type)), first(&h.content_encoding)));
+
+	let ip = get_or_add(&mut table.ip, ip);
+	let http_version = get_or_add(&mut table.http_version, &r.proto);
+	let method = get_or_add(&mut table.method, &r.method);
+	let domain = get_or_add(&mut table.domain, &r.host);
+	let path = table.add_path(p.strip_query_string(&r.uri));
+	let referer = get_or_add(&mut table.referer, p.strip_query_string(referer));
+	let user_agent = get_or_add(&mut table.user_agent, user_agent);
+	let content_type = get_or_add(&mut table.content_type, content_type);
+	let compression_type = get_or_add(&mut table.compression_type, compression_type);
+	let remote_user = get_or_add(&mut table.remote_user, &l.user_id);
+	let session_id = get_or_add(&mut table.session_id, "");
+	let country = get_or_add(&mut table.country, "");
+	Ok(LogLine { time, ip, http_version, method, domain, path, status_code: l.status, size: l.size, referer, user_agent, content_type, compression_type, remote_user, session_id, country })
+}
+
+#[derive(Deserialize)]
+struct TraefikLine<'a> {
+	#[serde(rename = "ClientHost", borrow)]
+	client_host: Cow<'a, str>,
+	#[serde(rename = "RequestProtocol", default, borrow)]
+	request_protocol: Cow<'a, str>,
+	#[serde(rename = "RequestMethod", borrow)]
+	request_method: Cow<'a, str>,
+	#[serde(rename = "RequestHost", default, borrow)]
+	request_host: Cow<'a, str>,
+	#[serde(rename = "RequestPath", borrow)]
+	request_path: Cow<'a, str>,
+	#[serde(rename = "DownstreamStatus")]
+	downstream_status: u32,
+	#[serde(rename = "DownstreamContentSize", default)]
+	downstream_content_size: u64,
+	#[serde(rename = "StartUTC", borrow)]
+	start_utc: Cow<'a, str>,
+	/// basic auth user name, `-` when there is none
+	#[serde(rename = "ClientUsername", default, borrow)]
+	client_username: Cow<'a, str>,
+	// headers are only logged when enabled by `accessLog.fields.headers`
+	#[serde(rename = "request_User-Agent", default, borrow)]
+	user_agent: Cow<'a, str>,
+	#[serde(rename = "request_Referer", default, borrow)]
+	referer: Cow<'a, str>,
+	#[serde(rename = "downstream_Content-Type", default, borrow)]
+	content_type: Cow<'a, str>,
+	#[serde(rename = "downstream_Content-Encoding", default, borrow)]
+	content_encoding: Cow<'a, str>,
+}
+
+// {"ClientHost":"1.2.3.4","DownstreamContentSize":1024,"DownstreamStatus":200,"RequestHost":"example.com","RequestMethod":"GET","RequestPath":"/","RequestProtocol":"HTTP/1.1","StartUTC":"2021-05-01T02:16:15.123456789Z","request_User-Agent":"curl/7.82.0"}
+
+/// JSON access log of the Traefik proxy
+pub fn parse_line_traefik(p: &LogParser, table: &mut GlobalTable, line: &str) -> Result<LogLine, ParseError> {
+	let l: TraefikLine = serde_json::from_str(line).map_err(json_error)?;
+
+	let time = DateTime::parse_from_rfc3339(&l.start_utc)
+		.map_err(|e| ParseError { offset: line.find(&*l.start_utc), ..ParseError::without_offset(ParseErrorKind::InvalidDate, Some(Field::Time), e.to_string()) })?
+		.to_utc();
+
+	let ip = get_or_add(&mut table.ip, &l.client_host);
+	let http_version = get_or_add(&mut table.http_version, &l.request_protocol);
+	let method = get_or_add(&mut table.method, &l.request_method);
+	let domain = get_or_add(&mut table.domain, &l.request_host);
+	let path = table.add_path(p.strip_query_string(&l.request_path));
+	let referer = get_or_add(&mut table.referer, p.strip_query_string(&l.referer));
+	let user_agent = get_or_add(&mut table.user_agent, &l.user_agent);
+	let content_type = get_or_add(&mut table.content_type, mime_type(&l.content_type));
+	let compression_type = get_or_add(&mut table.compression_type, &l.content_encoding);
+	let remote_user = get_or_add(&mut table.remote_user, no_dash(&l.client_username));
+	let session_id = get_or_add(&mut table.session_id, "");
+	let country = get_or_add(&mut table.country, "");
+	Ok(LogLine { time, ip, http_version, method, domain, path, status_code: l.downstream_status, size: l.downstream_content_size, referer, user_agent, content_type, compression_type, remote_user, session_id, country })
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use super::*;
+
+	fn parser(format: &str) -> LogParser {
+		LogParser::new(&FormatSpec::Preset(format.to_owned()), true).unwrap()
+	}
+
+	fn name(mapping: &HashMap<String, u32>, id: u32) -> &str {
+		mapping.iter().find(|&(_, &v)| v == id).unwrap().0
+	}
+
+	#[test]
+	fn parses_clf_date() {
+		let date = parse_clf_date(b"10/Oct/2000:13:55:36 -0700").unwrap();
+		assert_eq!(date.to_rfc3339(), "2000-10-10T13:55:36-07:00");
+		assert_eq!(date.to_utc().to_rfc3339(), "2000-10-10T20:55:36+00:00");
+		assert_eq!(parse_clf_date(b"01/Jan/2024:00:00:00 +0130").unwrap().to_utc().to_rfc3339(), "2023-12-31T22:30:00+00:00");
+	}
+
+	#[test]
+	fn rejects_invalid_clf_dates() {
+		for date in ["10/Okt/2000:13:55:36 -0700", "31/Feb/2000:13:55:36 -0700", "10/Oct/2000:25:55:36 -0700", "10/Oct/2000 13:55:36 -0700", "10/Oct/2000:13:55:36", "10/Oct/2000:13:55:36 0700", "1/Oct/2000:13:55:36 -0700"] {
+			assert_eq!(parse_clf_date(date.as_bytes()), None, "{}", date);
+		}
+	}
+
+	#[test]
+	fn parses_combined_line() {
+		let mut table = GlobalTable::new();
+		let l = parser("combined").parse_line(&mut table, r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a/b.html?x=1 HTTP/1.0" 200 2326 "http://www.example.com/start.html" "Mozilla/4.08 [en] (Win98; I ;Nav)""#).unwrap();
+		assert_eq!(l.time.to_rfc3339(), "2000-10-10T20:55:36+00:00");
+		assert_eq!(name(&table.ip, l.ip), "127.0.0.1");
+		assert_eq!(name(&table.remote_user, l.remote_user), "frank");
+		assert_eq!(name(&table.method, l.method), "GET");
+		assert_eq!(name(&table.http_version, l.http_version), "HTTP/1.0");
+		assert_eq!(table.path_list[l.path as usize], "/a/b.html");
+		assert_eq!((l.status_code, l.size), (200, 2326));
+		assert_eq!(name(&table.referer, l.referer), "http://www.example.com/start.html");
+		assert_eq!(name(&table.user_agent, l.user_agent), "Mozilla/4.08 [en] (Win98; I ;Nav)");
+	}
+
+	#[test]
+	fn parses_common_line() {
+		let mut table = GlobalTable::new();
+		let l = parser("common").parse_line(&mut table, r#"::1 - - [10/Oct/2000:13:55:36 +0000] "HEAD /x HTTP/1.1" 304 -"#).unwrap();
+		assert_eq!(name(&table.ip, l.ip), "::1");
+		assert_eq!(name(&table.remote_user, l.remote_user), "");
+		assert_eq!(name(&table.user_agent, l.user_agent), "");
+		assert_eq!((l.status_code, l.size), (304, 0));
+	}
+
+	#[test]
+	fn reports_clf_error_offsets() {
+		let p = parser("combined");
+		let mut table = GlobalTable::new();
+		let mut error = |line: &str| p.parse_line(&mut table, line).err().unwrap();
+
+		let e = error(r#"1.2.3.4 - - [10/Xyz/2000:13:55:36 -0700] "GET / HTTP/1.0" 200 1 "-" "ua""#);
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidDate, Some(Field::Time), Some(13)));
+		let e = error(r#"1.2.3.4 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 2x0 1 "-" "ua""#);
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidNumber, Some(Field::StatusCode), Some(58)));
+		let e = error(r#"1.2.3.4 - - [10/Oct/2000:13:55:36 -0700] "GET" 200 1 "-" "ua""#);
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::MalformedRequest, Some(Field::Path), Some(42)));
+		let e = error(r#"1.2.3.4 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200 1 "-" "ua"#);
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::MissingQuote, Some(Field::UserAgent), Some(68)));
+		let line = r#"1.2.3.4 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200"#;
+		let e = error(line);
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::UnexpectedEnd, Some(Field::Size), Some(line.len())));
+	}
+
+	#[test]
+	fn parses_caddy_line() {
+		let mut table = GlobalTable::new();
+		let line = r#"{"level":"info","ts":1646861401.52,"logger":"http.log.access","msg":"handled request","request":{"remote_ip":"10.0.0.1","client_ip":"1.2.3.4","remote_port":"41342","proto":"HTTP/2.0","method":"GET","host":"example.com","uri":"/a?b=1","headers":{"User-Agent":["curl/7.82.0"],"Referer":["https://example.com/"]}},"user_id":"frank","duration":0.0009,"size":10900,"status":404,"resp_headers":{"Content-Type":["text/html; charset=utf-8"],"Content-Encoding":["gzip"]}}"#;
+		let l = parser("caddy").parse_line(&mut table, line).unwrap();
+		assert_eq!(l.time.timestamp_millis(), 1646861401520);
+		assert_eq!(name(&table.ip, l.ip), "1.2.3.4");
+		assert_eq!(name(&table.domain, l.domain), "example.com");
+		assert_eq!(table.path_list[l.path as usize], "/a");
+		assert_eq!((l.status_code, l.size), (404, 10900));
+		assert_eq!(name(&table.user_agent, l.user_agent), "curl/7.82.0");
+		assert_eq!(name(&table.referer, l.referer), "https://example.com/");
+		assert_eq!(name(&table.content_type, l.content_type), "text/html");
+		assert_eq!(name(&table.compression_type, l.compression_type), "gzip");
+		assert_eq!(name(&table.remote_user, l.remote_user), "frank");
+	}
+
+	#[test]
+	fn reports_caddy_errors() {
+		let p = parser("caddy");
+		let mut table = GlobalTable::new();
+		let e = p.parse_line(&mut table, r#"{"ts":1646861401.52,"request":{"remote_ip":"1.2.3.4","proto":"HTTP/1.1","method":"GET","host":"a","uri":"/"},"status":"200","size":1}"#).err().unwrap();
+		assert_eq!((e.kind, e.offset), (ParseErrorKind::InvalidJson, Some(122)));
+		let e = p.parse_line(&mut table, r#"{"ts":1e30,"request":{"remote_ip":"1.2.3.4","proto":"HTTP/1.1","method":"GET","host":"a","uri":"/"},"status":200,"size":1}"#).err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidDate, Some(Field::Time), None));
+	}
+
+	#[test]
+	fn parses_traefik_line() {
+		let mut table = GlobalTable::new();
+		let line = r#"{"ClientHost":"1.2.3.4","ClientUsername":"-","DownstreamContentSize":1024,"DownstreamStatus":200,"RequestHost":"example.com","RequestMethod":"POST","RequestPath":"/form","RequestProtocol":"HTTP/1.1","StartUTC":"2021-05-01T02:16:15.123456789Z","request_User-Agent":"curl/7.82.0","downstream_Content-Type":"application/json"}"#;
+		let l = parser("traefik").parse_line(&mut table, line).unwrap();
+		assert_eq!(l.time.to_rfc3339(), "2021-05-01T02:16:15.123456789+00:00");
+		assert_eq!(name(&table.ip, l.ip), "1.2.3.4");
+		assert_eq!(name(&table.method, l.method), "POST");
+		assert_eq!(name(&table.domain, l.domain), "example.com");
+		assert_eq!(table.path_list[l.path as usize], "/form");
+		assert_eq!((l.status_code, l.size), (200, 1024));
+		assert_eq!(name(&table.remote_user, l.remote_user), "");
+		assert_eq!(name(&table.content_type, l.content_type), "application/json");
+	}
+
+	#[test]
+	fn reports_traefik_date_offset() {
+		let mut table = GlobalTable::new();
+		let line = r#"{"ClientHost":"1.2.3.4","DownstreamStatus":200,"RequestMethod":"GET","RequestPath":"/","StartUTC":"yesterday"}"#;
+		let e = parser("traefik").parse_line(&mut table, line).err().unwrap();
+		assert_eq!((e.kind, e.field, e.offset), (ParseErrorKind::InvalidDate, Some(Field::Time), Some(99)));
+		assert_eq!(&line[99..108], "yesterday");
+	}
+}
@@ -0,0 +1,110 @@
+//! Rewriting of the paths before the graph is computed, so the similar pages (`/user/123`, `/user/456`) become one node.
+//! The rules are applied in order, each one to the result of the previous one.
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+use crate::parser::GlobalTable;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum RewriteRule {
+	/// replaces the matches, `$1` in the replacement is the first capture group
+	Regex { pattern: String, replacement: String },
+	/// the prefix and all the paths under it become `target` (or the prefix itself)
+	Prefix { prefix: String, #[serde(default)] target: Option<String> },
+	/// path segments which are numbers or UUIDs become the placeholder (`:id` by default)
+	Ids { #[serde(default)] placeholder: Option<String> },
+}
+
+/// The rules used so far for the KSP web
+pub fn default_rules() -> Vec<RewriteRule> {
+	let regex = |pattern: &str, replacement: &str| RewriteRule::Regex { pattern: pattern.to_owned(), replacement: replacement.to_owned() };
+	let prefix = |prefix: &str, target: &str| RewriteRule::Prefix { prefix: prefix.to_owned(), target: Some(target.to_owned()) };
+	vec![
+		prefix("/priv", "admin"),
+		prefix("/admin", "admin"),
+		regex(r"^.*\.css$", "css"),
+		regex(r"^.*\.js$", "js"),
+		regex(r"^(.*)/index\.html$", "$1"),
+	]
+}
+
+enum CompiledRule {
+	Regex(Regex, String),
+	Prefix(String, String),
+	Ids(String),
+}
+
+pub struct PathRewriter {
+	rules: Vec<CompiledRule>,
+}
+
+impl PathRewriter {
+	pub fn new(rules: &[RewriteRule]) -> Result<PathRewriter, String> {
+		let rules = rules.iter().map(|r| Ok(match r {
+			RewriteRule::Regex { pattern, replacement } =>
+				CompiledRule::Regex(Regex::new(pattern).map_err(|e| format!("Invalid rewrite pattern {}: {}", pattern, e))?, replacement.clone()),
+			RewriteRule::Prefix { prefix, target } =>
+				CompiledRule::Prefix(prefix.trim_end_matches('/').to_owned(), target.clone().unwrap_or_else(|| prefix.trim_end_matches('/').to_owned())),
+			RewriteRule::Ids { placeholder } => CompiledRule::Ids(placeholder.clone().unwrap_or_else(|| ":id".to_owned())),
+		})).collect::<Result<Vec<_>, String>>()?;
+		Ok(PathRewriter { rules })
+	}
+
+	/// The changed paths are normalized, see `normalize`
+	pub fn rewrite(&self, original: &str) -> String {
+		let mut path = original.to_owned();
+		for rule in &self.rules {
+			path = match rule {
+				CompiledRule::Regex(r, replacement) => r.replace_all(&path, replacement.as_str()).into_owned(),
+				CompiledRule::Prefix(prefix, target) =>
+					if path.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')) { target.clone() } else { path },
+				CompiledRule::Ids(placeholder) =>
+					path.split('/').map(|segment| if is_id(segment) { placeholder.as_str() } else { segment }).collect::<Vec<_>>().join("/"),
+			};
+		}
+		if path == original { path } else { normalize(&path) }
+	}
+
+	/// Path id -> id of the rewritten path, for the paths which are changed by the rules.
+	/// The new paths (and their parents) are added to a copy of the paths, which is returned when there are any,
+	/// the table is shared by all the queries.
+	pub fn replacement_table(&self, table: &GlobalTable) -> (HashMap<u32, u32>, Option<GlobalTable>) {
+		let mut t = HashMap::new();
+		let mut scoped: Option<GlobalTable> = None;
+		for (p, path) in table.path_list.iter().enumerate() {
+			let rewritten = self.rewrite(path);
+			if rewritten != *path {
+				let id = match scoped.as_ref().unwrap_or(table).path.get(&rewritten) {
+					Some(&id) => id,
+					None => scoped.get_or_insert_with(|| copy_paths(table)).add_path(&rewritten),
+				};
+				t.insert(p as u32, id);
+			}
+		}
+		(t, scoped)
+	}
+}
+
+fn copy_paths(table: &GlobalTable) -> GlobalTable {
+	GlobalTable { path: table.path.clone(), path_list: table.path_list.clone(), ..GlobalTable::new() }
+}
+
+/// `x/a//b/` -> `/x/a/b`, so the graph can trim the paths to their parents.
+/// Names without a slash (`css`) are special nodes and are kept.
+fn normalize(path: &str) -> String {
+	if !path.contains('/') {
+		return path.to_owned();
+	}
+	format!("/{}", path.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>().join("/"))
+}
+
+/// `123`, `550e8400-e29b-41d4-a716-446655440000`
+fn is_id(segment: &str) -> bool {
+	let is_number = !segment.is_empty() && segment.bytes().all(|c| c.is_ascii_digit());
+	let is_uuid = segment.len() == 36 && segment.bytes().enumerate().all(|(i, c)|
+		if [8, 13, 18, 23].contains(&i) { c == b'-' } else { c.is_ascii_hexdigit() });
+	is_number || is_uuid
+}
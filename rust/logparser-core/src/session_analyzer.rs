@@ -0,0 +1,339 @@
+use std::{collections::{HashMap, BTreeSet}, ops::Add};
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+
+use crate::parser::*;
+use crate::session_key::{Identity, SessionKey, SessionKeyConfig};
+use crate::navigation::RefererPaths;
+use crate::classifier::{Classifier, RequestClass};
+
+/// The request of a page view, other than the path
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionDetails {
+	pub status_code: u16,
+	pub method: u32,
+	pub domain: u32,
+	pub referer: u32,
+	pub content_type: u32,
+	/// size of the page itself, without the assets
+	pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+	/// the requests were joined by this, see `session_key`
+	pub identity: Identity,
+	/// of the first request
+	pub ip: u32,
+	pub user_agent: u32,
+	pub referer: u32,
+	pub country: u32,
+	#[serde(with = "chrono::serde::ts_milliseconds")]
+	pub start_time: DateTime<Utc>,
+	#[serde(with = "chrono::serde::ts_milliseconds")]
+	pub end_time: DateTime<Utc>,
+	/// of any request, the assets of the last page are loaded after `end_time`
+	#[serde(with = "chrono::serde::ts_milliseconds")]
+	pub last_request_time: DateTime<Utc>,
+	// seconds since startime
+	pub access_times: Vec<u32>,
+	/// list of html pages (paths) accessed by this session
+	pub actions: Vec<u32>,
+	/// the requests of the actions, `details[i]` belongs to `actions[i]`
+	pub details: Vec<ActionDetails>,
+	/// index of the action in the referer of each action (`None` for the entry pages),
+	/// empty unless the navigation tree is built (see `navigation`)
+	pub parents: Vec<Option<u32>>,
+	pub total_requests: u32,
+	pub total_bytes: u64,
+}
+
+/// Options deciding how the requests are joined into sessions, all the logs added to the same sessions must use the same ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionOptions {
+	pub session_key: SessionKeyConfig,
+	pub referer_tree: bool,
+}
+
+/// Sessions which may continue with the next requests, kept between the loads so that a visit isn't split at the end of a log file
+#[derive(Default)]
+pub struct Sessionizer {
+	sessions: HashMap<Identity, Session>,
+	/// (end_time, identity) of the open sessions, the oldest one is closed first
+	session_age: BTreeSet<(DateTime<Utc>, Identity)>,
+	referer_paths: RefererPaths,
+	/// options of the sessions loaded so far, `None` before the first load
+	options: Option<SessionOptions>,
+}
+
+impl Sessionizer {
+	/// Continues the sessions, e.g. from a snapshot
+	pub fn from_open_sessions(open_sessions: Vec<Session>, options: Option<SessionOptions>) -> Sessionizer {
+		Sessionizer {
+			session_age: open_sessions.iter().map(|s| (s.end_time, s.identity)).collect(),
+			sessions: open_sessions.into_iter().map(|s| (s.identity, s)).collect(),
+			referer_paths: RefererPaths::default(),
+			options,
+		}
+	}
+
+	pub fn options(&self) -> Option<&SessionOptions> {
+		self.options.as_ref()
+	}
+
+	/// Fails when the earlier loads used other options, otherwise the next logs would be joined into sessions differently
+	pub fn set_options(&mut self, options: SessionOptions) -> Result<(), String> {
+		match &self.options {
+			Some(previous) if *previous != options => {
+				let describe = |o: &SessionOptions| format!("session key {:?}{}", o.session_key, if o.referer_tree { " with the referer tree" } else { "" });
+				Err(format!("The loaded sessions use the {}, the logs can not be added with the {}", describe(previous), describe(&options)))
+			},
+			_ => {
+				self.options = Some(options);
+				Ok(())
+			},
+		}
+	}
+
+	/// ordered by the time of the last page
+	pub fn open_sessions(&self) -> impl Iterator<Item = &Session> {
+		self.session_age.iter().map(|(_, identity)| &self.sessions[identity])
+	}
+
+	pub fn len(&self) -> usize {
+		self.sessions.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.sessions.is_empty()
+	}
+
+	/// Closes all the open sessions
+	pub fn flush(&mut self) -> Vec<Session> {
+		let identities = std::mem::take(&mut self.session_age);
+		identities.into_iter().map(|(_, identity)| self.sessions.remove(&identity).unwrap()).collect()
+	}
+}
+
+/// Adds the requests to the sessions and returns the closed ones. The lines should be ordered by time (see `merge`),
+/// a request older than the last page of its session gets the time of that page.
+/// The sessions which are still open after the lines stay in `sessionizer`, they continue with the requests of the next call
+/// or are closed by `Sessionizer::flush`. Sessions longer than 10000 × `max_age` are closed too, the next request starts a new one.
+pub fn get_sessions(
+	sessionizer: &mut Sessionizer,
+	table: &GlobalTable,
+	loglines: &[LogLine],
+	max_age: u32,
+	key: &mut dyn SessionKey,
+	referer_tree: bool,
+	classifier: &mut Classifier,
+) -> Vec<Session> {
+	let Sessionizer { sessions, session_age, referer_paths, .. } = sessionizer;
+	let mut result: Vec<Session> = vec![];
+
+	for logline in loglines.iter() {
+		// before the request is added, it must not continue a session which has already expired
+		while let Some(&(time, oldest_session)) = session_age.first() {
+			if time.timestamp() >= logline.time.timestamp() - (max_age as i64) {
+				break;
+			}
+			let s = sessions.remove(&oldest_session).unwrap();
+			result.push(s);
+			session_age.pop_first();
+		}
+
+		let class = classifier.classify(logline, table);
+		if class == RequestClass::Ignored {
+			continue;
+		}
+		let is_meaningless = class == RequestClass::Asset;
+		let session_id = key.identity(logline, table);
+
+		// a session which never pauses for `max_age` (e.g. a monitoring tool) is split, the access times must fit into u32
+		let max_length = (max_age as i64 * 10000).min(u32::MAX as i64);
+		let too_long = sessions.get(&session_id)
+			.filter(|s| logline.time.max(s.end_time).timestamp() - s.start_time.timestamp() > max_length)
+			.map(|s| s.end_time);
+		if let Some(end_time) = too_long {
+			session_age.remove(&(end_time, session_id));
+			result.push(sessions.remove(&session_id).unwrap());
+		}
+
+		{
+			let s = if let Some(s) = sessions.get_mut(&session_id) {
+				s
+			} else {
+				if is_meaningless {
+					// don't create session with meaningless request
+					continue;
+				}
+				let s = Session {
+					identity: session_id,
+					ip: logline.ip,
+					user_agent: logline.user_agent,
+					referer: logline.referer,
+					country: logline.country,
+					end_time: logline.time,
+					start_time: logline.time,
+					last_request_time: logline.time,
+					access_times: vec![],
+					actions: vec![],
+					details: vec![],
+					parents: vec![],
+					total_requests: 0,
+					total_bytes: 0,
+				};
+				sessions.insert(session_id, s);
+				sessions.get_mut(&session_id).unwrap()
+			};
+
+			// the times of a session never go back, even when the logs of two loads overlap
+			let time = logline.time.max(s.end_time);
+			let acctime = time.timestamp() - s.start_time.timestamp();
+
+			let is_meaningless = is_meaningless || (
+				class == RequestClass::ProbablyAsset &&
+					!s.actions.is_empty() &&
+					s.end_time.add(chrono::Duration::seconds(10)) > time);
+
+			s.total_requests += 1;
+			s.total_bytes += logline.size;
+			s.last_request_time = s.last_request_time.max(time);
+
+			if !is_meaningless {
+				// only track meaningfull actions (not resource loading)
+				session_age.remove(&(s.end_time, session_id));
+				s.access_times.push(acctime as u32);
+				s.end_time = time;
+				if referer_tree {
+					let parent = referer_paths.path_id(logline.referer, logline.domain, table)
+						.and_then(|p| s.actions.iter().rposition(|&a| a == p));
+					s.parents.push(parent.map(|i| i as u32));
+				}
+				s.actions.push(logline.path);
+				s.details.push(ActionDetails {
+					status_code: logline.status_code.min(u16::MAX as u32) as u16,
+					method: logline.method,
+					domain: logline.domain,
+					referer: logline.referer,
+					content_type: logline.content_type,
+					bytes: logline.size,
+				});
+				session_age.insert((s.end_time, session_id));
+			}
+
+		}
+	}
+
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{classifier::ClassifierConfig, session_key::IpUserAgentKey};
+
+	const MAX_AGE: u32 = 60;
+
+	/// Combined log lines of `(second, ip, path)`
+	fn parse(table: &mut GlobalTable, requests: &[(i64, &str, &str)]) -> Vec<LogLine> {
+		let parser = LogParser::new(&FormatSpec::Preset("combined".to_owned()), true).unwrap();
+		requests.iter().map(|&(sec, ip, path)| {
+			let time = DateTime::from_timestamp(sec, 0).unwrap().format("%d/%b/%Y:%H:%M:%S +0000");
+			parser.parse_line(table, &format!(r#"{} - - [{}] "GET {} HTTP/1.1" 200 100 "-" "Mozilla/5.0""#, ip, time, path)).unwrap()
+		}).collect()
+	}
+
+	fn run(sessionizer: &mut Sessionizer, table: &GlobalTable, lines: &[LogLine], max_age: u32) -> Vec<Session> {
+		let mut classifier = Classifier::new(&ClassifierConfig::default()).unwrap();
+		get_sessions(sessionizer, table, lines, max_age, &mut IpUserAgentKey, false, &mut classifier)
+	}
+
+	fn paths<'a>(s: &Session, table: &'a GlobalTable) -> Vec<&'a str> {
+		s.actions.iter().map(|&p| table.path_list[p as usize].as_str()).collect()
+	}
+
+	#[test]
+	fn continues_sessions_in_the_next_call() {
+		let mut table = GlobalTable::new();
+		let first = parse(&mut table, &[(0, "1.1.1.1", "/a"), (5, "2.2.2.2", "/x")]);
+		let second = parse(&mut table, &[(10, "1.1.1.1", "/b"), (20, "1.1.1.1", "/style.css")]);
+		let mut sessionizer = Sessionizer::default();
+
+		assert!(run(&mut sessionizer, &table, &first, MAX_AGE).is_empty());
+		assert_eq!(sessionizer.len(), 2);
+		assert!(run(&mut sessionizer, &table, &second, MAX_AGE).is_empty());
+		assert_eq!(sessionizer.len(), 2);
+
+		let sessions = sessionizer.flush();
+		assert!(sessionizer.is_empty());
+		assert_eq!(sessions.len(), 2);
+		let s = sessions.iter().find(|s| s.actions.len() == 2).unwrap();
+		assert_eq!(paths(s, &table), ["/a", "/b"]);
+		assert_eq!(s.access_times, [0, 10]);
+		assert_eq!(s.total_requests, 3);
+		assert_eq!((s.end_time.timestamp(), s.last_request_time.timestamp()), (10, 20));
+	}
+
+	#[test]
+	fn closes_expired_sessions_in_the_next_call() {
+		let mut table = GlobalTable::new();
+		let first = parse(&mut table, &[(0, "1.1.1.1", "/a"), (10, "2.2.2.2", "/x")]);
+		let second = parse(&mut table, &[(65, "1.1.1.1", "/b")]);
+		let mut sessionizer = Sessionizer::default();
+
+		run(&mut sessionizer, &table, &first, MAX_AGE);
+		// the line at 65 expires the session which ended at 0, not the one which ended at 10
+		let closed = run(&mut sessionizer, &table, &second, MAX_AGE);
+		assert_eq!(closed.len(), 1);
+		assert_eq!(paths(&closed[0], &table), ["/a"]);
+		assert_eq!(sessionizer.open_sessions().map(|s| paths(s, &table)).collect::<Vec<_>>(), [vec!["/x"], vec!["/b"]]);
+	}
+
+	#[test]
+	fn keeps_the_times_of_older_lines_in_order() {
+		let mut table = GlobalTable::new();
+		let first = parse(&mut table, &[(0, "1.1.1.1", "/a"), (30, "1.1.1.1", "/b")]);
+		// e.g. the overlapping logs of the next load
+		let second = parse(&mut table, &[(20, "1.1.1.1", "/c")]);
+		let mut sessionizer = Sessionizer::default();
+
+		run(&mut sessionizer, &table, &first, MAX_AGE);
+		run(&mut sessionizer, &table, &second, MAX_AGE);
+		let s = &sessionizer.flush()[0];
+		assert_eq!(paths(s, &table), ["/a", "/b", "/c"]);
+		assert_eq!(s.access_times, [0, 30, 30]);
+		assert_eq!(s.end_time.timestamp(), 30);
+	}
+
+	#[test]
+	fn does_not_start_sessions_with_assets() {
+		let mut table = GlobalTable::new();
+		let lines = parse(&mut table, &[(0, "1.1.1.1", "/app.js"), (1, "1.1.1.1", "/a"), (2, "1.1.1.1", "/a.png"), (30, "1.1.1.1", "/b.png")]);
+		let mut sessionizer = Sessionizer::default();
+
+		run(&mut sessionizer, &table, &lines, MAX_AGE);
+		let s = &sessionizer.flush()[0];
+		// the image loaded by the page is an asset, the one opened later is a page view
+		assert_eq!(paths(s, &table), ["/a", "/b.png"]);
+		assert_eq!(s.total_requests, 3);
+	}
+
+	#[test]
+	fn splits_sessions_longer_than_the_limit() {
+		let mut table = GlobalTable::new();
+		// never pauses for max_age = 1 second, the limit is 10000 seconds
+		let requests: Vec<_> = (0..10005).map(|sec| (sec, "1.1.1.1", if sec % 2 == 0 { "/a" } else { "/b" })).collect();
+		let lines = parse(&mut table, &requests);
+		let mut sessionizer = Sessionizer::default();
+
+		let closed = run(&mut sessionizer, &table, &lines, 1);
+		assert_eq!(closed.len(), 1);
+		assert_eq!(closed[0].actions.len(), 10001);
+		assert_eq!(closed[0].access_times.last(), Some(&10000));
+		let open = sessionizer.flush();
+		assert_eq!(open[0].start_time.timestamp(), 10001);
+		assert_eq!(open[0].access_times, [0, 1, 2, 3]);
+	}
+}
@@ -0,0 +1,191 @@
+//! Strategies deciding which requests belong to the same visitor
+use std::net::IpAddr;
+
+use serde::{Serialize, Deserialize};
+
+use crate::parser::{GlobalTable, LogLine};
+
+/// Requests with the same identity are joined into sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Identity {
+	IpUserAgent { ip: u32, user_agent: u32 },
+	SessionId(u32),
+	RemoteUser(u32),
+	/// masked IP address (IPv4 is mapped to IPv6) and the user agent
+	Network { prefix: u128, user_agent: u32 },
+}
+
+pub trait SessionKey {
+	fn identity(&mut self, line: &LogLine, table: &GlobalTable) -> Identity;
+}
+
+/// The default, users behind a NAT with the same browser are joined
+pub struct IpUserAgentKey;
+
+impl SessionKey for IpUserAgentKey {
+	fn identity(&mut self, line: &LogLine, _table: &GlobalTable) -> Identity {
+		Identity::IpUserAgent { ip: line.ip, user_agent: line.user_agent }
+	}
+}
+
+/// Session cookie (the `session_id` field), ip + user agent when it's not set
+pub struct SessionIdKey;
+
+impl SessionKey for SessionIdKey {
+	fn identity(&mut self, line: &LogLine, table: &GlobalTable) -> Identity {
+		if table.session_id.get("") == Some(&line.session_id) {
+			IpUserAgentKey.identity(line, table)
+		} else {
+			Identity::SessionId(line.session_id)
+		}
+	}
+}
+
+/// Authenticated user, ip + user agent for the anonymous requests
+pub struct RemoteUserKey;
+
+impl SessionKey for RemoteUserKey {
+	fn identity(&mut self, line: &LogLine, table: &GlobalTable) -> Identity {
+		if table.remote_user.get("") == Some(&line.remote_user) {
+			IpUserAgentKey.identity(line, table)
+		} else {
+			Identity::RemoteUser(line.remote_user)
+		}
+	}
+}
+
+/// Network prefix + user agent, keeps the session when a mobile user gets another address from the same network
+pub struct IpPrefixKey {
+	ipv4_prefix: u8,
+	ipv6_prefix: u8,
+	/// masked address for each IP id
+	prefixes: Vec<Option<u128>>,
+}
+
+impl IpPrefixKey {
+	pub fn new(ipv4_prefix: u8, ipv6_prefix: u8) -> IpPrefixKey {
+		IpPrefixKey { ipv4_prefix: ipv4_prefix.min(32), ipv6_prefix: ipv6_prefix.min(128), prefixes: vec![] }
+	}
+
+	fn mask(&self, ip: &str) -> Option<u128> {
+		let (addr, prefix) = match ip.parse::<IpAddr>().ok()? {
+			IpAddr::V4(a) => (a.to_ipv6_mapped(), 96 + self.ipv4_prefix as u32),
+			IpAddr::V6(a) => (a, self.ipv6_prefix as u32),
+		};
+		Some(u128::from(addr) & u128::MAX.checked_shl(128 - prefix).unwrap_or(0))
+	}
+}
+
+impl SessionKey for IpPrefixKey {
+	fn identity(&mut self, line: &LogLine, table: &GlobalTable) -> Identity {
+		if line.ip as usize >= self.prefixes.len() {
+			// only the addresses added to the table since the last time are masked
+			let new: Vec<(usize, Option<u128>)> = table.ip.iter()
+				.filter(|&(_, &id)| id as usize >= self.prefixes.len())
+				.map(|(ip, &id)| (id as usize, self.mask(ip)))
+				.collect();
+			let len = new.iter().map(|&(id, _)| id + 1).chain([line.ip as usize + 1]).max().unwrap();
+			self.prefixes.resize(len, None);
+			for (id, prefix) in new {
+				self.prefixes[id] = prefix;
+			}
+		}
+		match self.prefixes[line.ip as usize] {
+			Some(prefix) => Identity::Network { prefix, user_agent: line.user_agent },
+			// not an address (the log may contain host names), only the same strings are joined
+			None => IpUserAgentKey.identity(line, table),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(tag = "key", rename_all = "snake_case")]
+pub enum SessionKeyConfig {
+	#[default]
+	IpUserAgent,
+	/// the `session_id` field of the log format
+	SessionId,
+	/// the `remote_user` field of the log format
+	RemoteUser,
+	IpPrefix {
+		#[serde(default = "default_ipv4_prefix")]
+		ipv4_prefix: u8,
+		#[serde(default = "default_ipv6_prefix")]
+		ipv6_prefix: u8,
+	},
+}
+
+fn default_ipv4_prefix() -> u8 { 24 }
+fn default_ipv6_prefix() -> u8 { 64 }
+
+impl SessionKeyConfig {
+	pub fn build(&self) -> Box<dyn SessionKey> {
+		match *self {
+			SessionKeyConfig::IpUserAgent => Box::new(IpUserAgentKey),
+			SessionKeyConfig::SessionId => Box::new(SessionIdKey),
+			SessionKeyConfig::RemoteUser => Box::new(RemoteUserKey),
+			SessionKeyConfig::IpPrefix { ipv4_prefix, ipv6_prefix } => Box::new(IpPrefixKey::new(ipv4_prefix, ipv6_prefix)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parser::{get_or_add, FormatSpec, LogParser};
+
+	/// Combined log line of the IP address and the authenticated user (`-` for none)
+	fn line(table: &mut GlobalTable, ip: &str, remote_user: &str) -> LogLine {
+		let parser = LogParser::new(&FormatSpec::Preset("combined".to_owned()), true).unwrap();
+		parser.parse_line(table, &format!(r#"{} - {} [01/May/2021:10:00:00 +0000] "GET / HTTP/1.1" 200 100 "-" "Mozilla/5.0""#, ip, remote_user)).unwrap()
+	}
+
+	#[test]
+	fn masks_the_addresses() {
+		let mut table = GlobalTable::new();
+		let mut key = IpPrefixKey::new(24, 64);
+		let mut identity = |ip: &str| {
+			let l = line(&mut table, ip, "-");
+			key.identity(&l, &table)
+		};
+		let v4 = identity("10.0.0.1");
+		assert_eq!(v4, Identity::Network { prefix: u128::from("::ffff:10.0.0.0".parse::<std::net::Ipv6Addr>().unwrap()), user_agent: 1 });
+		assert_eq!(identity("10.0.0.200"), v4);
+		assert_ne!(identity("10.0.1.1"), v4);
+		let v6 = identity("2001:db8:1:2::1");
+		assert_eq!(identity("2001:db8:1:2:ffff::5"), v6);
+		assert_ne!(identity("2001:db8:1:3::1"), v6);
+		assert_ne!(v4, v6);
+		// host names are not masked
+		assert!(matches!(identity("proxy.example.com"), Identity::IpUserAgent { .. }));
+	}
+
+	#[test]
+	fn masks_the_addresses_added_in_the_next_chunks() {
+		let mut table = GlobalTable::new();
+		let mut key = IpPrefixKey::new(16, 48);
+		let first = line(&mut table, "192.168.1.1", "-");
+		let first = key.identity(&first, &table);
+		for i in 0..100 {
+			line(&mut table, &format!("172.16.{}.1", i), "-");
+		}
+		let next = line(&mut table, "192.168.200.5", "-");
+		assert_eq!(key.identity(&next, &table), first);
+		let other = line(&mut table, "172.16.50.1", "-");
+		assert_ne!(key.identity(&other, &table), first);
+	}
+
+	#[test]
+	fn falls_back_to_the_ip_and_user_agent() {
+		let mut table = GlobalTable::new();
+		let anonymous = line(&mut table, "1.1.1.1", "-");
+		let frank = line(&mut table, "1.1.1.1", "frank");
+		let ip_user_agent = Identity::IpUserAgent { ip: anonymous.ip, user_agent: anonymous.user_agent };
+		assert_eq!(RemoteUserKey.identity(&anonymous, &table), ip_user_agent);
+		assert_eq!(RemoteUserKey.identity(&frank, &table), Identity::RemoteUser(frank.remote_user));
+
+		assert_eq!(SessionIdKey.identity(&anonymous, &table), ip_user_agent);
+		let cookie = LogLine { session_id: get_or_add(&mut table.session_id, "abc"), ..line(&mut table, "2.2.2.2", "-") };
+		assert_eq!(SessionIdKey.identity(&cookie, &table), Identity::SessionId(cookie.session_id));
+	}
+}
@@ -0,0 +1,187 @@
+//! Aggregate metrics of the sessions: how long they are, how many pages they visit, and who comes back
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{session_analyzer::Session, stats::{time_bucket, StatsOptions}};
+
+/// Upper bounds (exclusive) of the histogram buckets, the last bucket has the larger values
+pub const DURATION_BUCKETS: [u64; 8] = [10, 30, 60, 2 * 60, 5 * 60, 10 * 60, 30 * 60, 60 * 60];
+pub const PAGES_BUCKETS: [u64; 7] = [2, 3, 4, 5, 10, 20, 50];
+pub const BYTES_BUCKETS: [u64; 5] = [10_000, 100_000, 1_000_000, 10_000_000, 100_000_000];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Distribution {
+	/// upper bounds (exclusive) of the buckets of `histogram`, the last bucket has the larger values
+	pub buckets: Vec<u64>,
+	pub histogram: Vec<u32>,
+	pub mean: f64,
+	pub p25: u64,
+	pub p50: u64,
+	pub p75: u64,
+	pub p90: u64,
+	pub p99: u64,
+}
+
+impl Distribution {
+	fn new(mut values: Vec<u64>, buckets: &[u64]) -> Distribution {
+		values.sort_unstable();
+		let mut histogram = vec![0; buckets.len() + 1];
+		for &v in &values {
+			histogram[buckets.iter().position(|&b| v < b).unwrap_or(buckets.len())] += 1;
+		}
+		let percentile = |p: usize| if values.is_empty() { 0 } else { values[(values.len() - 1) * p / 100] };
+		Distribution {
+			buckets: buckets.to_vec(),
+			histogram,
+			mean: if values.is_empty() { 0.0 } else { values.iter().sum::<u64>() as f64 / values.len() as f64 },
+			p25: percentile(25),
+			p50: percentile(50),
+			p75: percentile(75),
+			p90: percentile(90),
+			p99: percentile(99),
+		}
+	}
+}
+
+/// Sessions started in a time bucket
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionSummaryRow {
+	/// in `resolution_sec` units since `SessionSummary::start_time`
+	pub time: u32,
+	pub sessions: u32,
+	/// sessions with a single page
+	pub bounces: u32,
+	/// sessions of visitors which were not seen before
+	pub new_visitors: u32,
+	pub returning_visitors: u32,
+	pub median_duration: u64,
+	pub median_pages: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+	pub sessions: u32,
+	pub bounces: u32,
+	/// seconds from the first to the last request
+	pub duration: Distribution,
+	pub pages: Distribution,
+	pub bytes: Distribution,
+	/// the buckets with at least one session
+	pub rows: Vec<SessionSummaryRow>,
+	/// in `resolution_sec` units since 1970-01-01 local time of `StatsOptions::timezone`
+	pub start_time: i64,
+	pub end_time: i64,
+}
+
+/// The visitors are recognized by the session identity (see `session_key`)
+pub fn session_summary(sessions: &[Session], opt: &StatsOptions) -> SessionSummary {
+	let duration = |s: &Session| (s.last_request_time - s.start_time).num_seconds().max(0) as u64;
+
+	let mut by_start: Vec<&Session> = sessions.iter().collect();
+	by_start.sort_by_key(|s| s.start_time);
+	let mut seen = HashSet::new();
+	// time bucket -> (row, durations, pages)
+	let mut buckets: BTreeMap<i64, (SessionSummaryRow, Vec<u64>, Vec<u64>)> = BTreeMap::new();
+	for s in by_start {
+		let (row, durations, pages) = buckets.entry(time_bucket(s.start_time, opt.resolution_sec, &opt.timezone)).or_default();
+		row.sessions += 1;
+		row.bounces += (s.actions.len() == 1) as u32;
+		if seen.insert(s.identity) {
+			row.new_visitors += 1;
+		} else {
+			row.returning_visitors += 1;
+		}
+		durations.push(duration(s));
+		pages.push(s.actions.len() as u64);
+	}
+
+	let start_time = buckets.keys().next().copied().unwrap_or(0);
+	let end_time = buckets.keys().next_back().copied().unwrap_or(0);
+	let rows = buckets.into_iter().map(|(time, (mut row, mut durations, mut pages))| {
+		durations.sort_unstable();
+		pages.sort_unstable();
+		row.time = (time - start_time) as u32;
+		row.median_duration = durations[durations.len() / 2];
+		row.median_pages = pages[pages.len() / 2];
+		row
+	}).collect();
+
+	SessionSummary {
+		sessions: sessions.len() as u32,
+		bounces: sessions.iter().filter(|s| s.actions.len() == 1).count() as u32,
+		duration: Distribution::new(sessions.iter().map(duration).collect(), &DURATION_BUCKETS),
+		pages: Distribution::new(sessions.iter().map(|s| s.actions.len() as u64).collect(), &PAGES_BUCKETS),
+		bytes: Distribution::new(sessions.iter().map(|s| s.total_bytes).collect(), &BYTES_BUCKETS),
+		rows,
+		start_time,
+		end_time,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::{DateTime, TimeDelta};
+
+	use super::*;
+	use crate::session_key::Identity;
+
+	/// Session of the visitor `ip` which started at `start` seconds, lasted `duration` seconds and visited `pages` pages
+	fn session(ip: u32, start: i64, duration: i64, pages: u32, bytes: u64) -> Session {
+		let start_time = DateTime::from_timestamp(start, 0).unwrap();
+		let end_time = start_time + TimeDelta::seconds(duration);
+		Session {
+			identity: Identity::IpUserAgent { ip, user_agent: 0 },
+			ip, user_agent: 0, referer: 0, country: 0,
+			start_time, end_time, last_request_time: end_time,
+			actions: vec![0; pages as usize],
+			access_times: vec![0; pages as usize],
+			details: vec![],
+			parents: vec![],
+			total_requests: pages,
+			total_bytes: bytes,
+		}
+	}
+
+	#[test]
+	fn computes_the_percentiles() {
+		let d = Distribution::new((1..=100).rev().collect(), &DURATION_BUCKETS);
+		assert_eq!((d.p25, d.p50, d.p75, d.p90, d.p99), (25, 50, 75, 90, 99));
+		assert_eq!(d.mean, 50.5);
+		let d = Distribution::new(vec![7], &DURATION_BUCKETS);
+		assert_eq!((d.p25, d.p99, d.mean), (7, 7, 7.0));
+		let d = Distribution::new(vec![], &DURATION_BUCKETS);
+		assert_eq!((d.p50, d.mean), (0, 0.0));
+		assert!(d.histogram.iter().all(|&c| c == 0));
+	}
+
+	#[test]
+	fn puts_the_bucket_bounds_into_the_next_bucket() {
+		let d = Distribution::new(vec![0, 9, 10, 29, 30, 1000], &[10, 30]);
+		assert_eq!(d.histogram, [2, 2, 2]);
+		assert_eq!(d.buckets, [10, 30]);
+		let d = Distribution::new(vec![1, 2, 49, 50], &PAGES_BUCKETS);
+		assert_eq!(d.histogram, [1, 1, 0, 0, 0, 0, 1, 1]);
+	}
+
+	#[test]
+	fn summarizes_the_time_buckets() {
+		let sessions = [
+			session(1, 3700, 40, 2, 5_000),
+			session(1, 0, 0, 1, 20_000),
+			session(2, 100, 600, 3, 200_000),
+			session(3, 4 * 3600 + 10, 5, 1, 0),
+		];
+		let summary = session_summary(&sessions, &StatsOptions::new(3600, 0, 0));
+		assert_eq!((summary.sessions, summary.bounces), (4, 2));
+		assert_eq!((summary.start_time, summary.end_time), (0, 4));
+		assert_eq!(summary.duration.histogram, [2, 0, 1, 0, 0, 0, 1, 0, 0]);
+		assert_eq!(summary.pages.histogram, [2, 1, 1, 0, 0, 0, 0, 0]);
+		assert_eq!(summary.bytes.histogram, [2, 1, 1, 0, 0, 0]);
+		let rows: Vec<_> = summary.rows.iter()
+			.map(|r| (r.time, r.sessions, r.bounces, r.new_visitors, r.returning_visitors, r.median_duration, r.median_pages))
+			.collect();
+		// the buckets without sessions are left out
+		assert_eq!(rows, [(0, 2, 1, 2, 0, 600, 3), (1, 1, 0, 0, 1, 40, 2), (4, 1, 1, 1, 0, 5, 1)]);
+	}
+}
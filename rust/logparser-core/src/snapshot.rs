@@ -0,0 +1,267 @@
+//! Binary snapshot of the parsed sessions and the symbol table, loading it is much faster than parsing the logs again.
+//!
+//! The file starts with `SNAPSHOT_MAGIC` and the little-endian u32 `SNAPSHOT_VERSION`, the rest is gzip compressed bincode
+//! (with variable-length integers). Each string is stored once in the table of its id, the sessions only contain the ids.
+//! The options used to build the sessions are stored too, the logs added to a snapshot must use the same ones.
+use std::{collections::HashMap, io::{Read, Write}};
+
+use bincode::Options;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Serialize, Deserialize};
+
+use crate::{parser::GlobalTable, session_analyzer::{Session, SessionOptions, Sessionizer}, session_key::Identity};
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"LSANKEY\0";
+/// Incremented on every change of the format, older snapshots are rejected
+pub const SNAPSHOT_VERSION: u32 = 3;
+/// Largest uncompressed snapshot, a corrupted length can't make the reader allocate more
+const SNAPSHOT_SIZE_LIMIT: u64 = 2 << 30;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<S, O> {
+	/// `SessionKeyConfig` as JSON (bincode can't read the tagged enum), `None` when no logs were loaded
+	session_key: Option<String>,
+	referer_tree: bool,
+	/// the tables of `table_fields` in that order, `tables[t][id]` is the string with the id
+	tables: Vec<Vec<Option<String>>>,
+	/// the path ids are the indices
+	path_list: Vec<String>,
+	sessions: S,
+	/// sessions of the `Sessionizer`, which continue in the next logs
+	open_sessions: O,
+}
+
+fn table_fields(t: &GlobalTable) -> [&HashMap<String, u32>; 11] {
+	[&t.ip, &t.http_version, &t.method, &t.domain, &t.referer, &t.user_agent, &t.content_type, &t.compression_type, &t.remote_user, &t.session_id, &t.country]
+}
+
+fn to_list(mapping: &HashMap<String, u32>) -> Vec<Option<String>> {
+	let mut list = vec![None; mapping.values().max().map_or(0, |&max| max as usize + 1)];
+	for (s, &id) in mapping {
+		list[id as usize] = Some(s.clone());
+	}
+	list
+}
+
+fn bincode_options() -> impl Options {
+	bincode::DefaultOptions::new().with_limit(SNAPSHOT_SIZE_LIMIT)
+}
+
+/// Checks that the ids of the session are in the tables, so that the stats can't index out of them
+fn validate_session(s: &Session, tables: &[Vec<Option<String>>], path_count: usize) -> Result<(), String> {
+	let [ip, _, method, domain, referer, user_agent, content_type, _, remote_user, session_id, country] = tables else {
+		return Err("unexpected number of string tables".to_owned());
+	};
+	let check = |table: &Vec<Option<String>>, id: u32, name: &str| match table.get(id as usize) {
+		Some(Some(_)) => Ok(()),
+		_ => Err(format!("unknown {} id {}", name, id)),
+	};
+	match s.identity {
+		Identity::IpUserAgent { ip: i, user_agent: ua } => check(ip, i, "ip").and(check(user_agent, ua, "user agent"))?,
+		Identity::SessionId(id) => check(session_id, id, "session id")?,
+		Identity::RemoteUser(id) => check(remote_user, id, "remote user")?,
+		Identity::Network { user_agent: ua, .. } => check(user_agent, ua, "user agent")?,
+	}
+	check(ip, s.ip, "ip")?;
+	check(user_agent, s.user_agent, "user agent")?;
+	check(referer, s.referer, "referer")?;
+	check(country, s.country, "country")?;
+	if s.access_times.len() != s.actions.len() || s.details.len() != s.actions.len() || !(s.parents.is_empty() || s.parents.len() == s.actions.len()) {
+		return Err("different number of actions and their details".to_owned());
+	}
+	if let Some(&path) = s.actions.iter().find(|&&p| p as usize >= path_count) {
+		return Err(format!("unknown path id {}", path));
+	}
+	for d in &s.details {
+		check(method, d.method, "method")?;
+		check(domain, d.domain, "domain")?;
+		check(referer, d.referer, "referer")?;
+		check(content_type, d.content_type, "content type")?;
+	}
+	if s.parents.iter().enumerate().any(|(i, p)| p.is_some_and(|p| p as usize >= i)) {
+		return Err("parent page after its child".to_owned());
+	}
+	Ok(())
+}
+
+pub fn write_snapshot(output: impl Write, table: &GlobalTable, sessions: &[Session], sessionizer: &Sessionizer) -> Result<(), String> {
+	let mut output = output;
+	output.write_all(SNAPSHOT_MAGIC).and_then(|_| output.write_all(&SNAPSHOT_VERSION.to_le_bytes()))
+		.map_err(|e| format!("Could not write the snapshot: {}", e))?;
+
+	let options = sessionizer.options();
+	let snapshot = Snapshot {
+		session_key: options.map(|o| serde_json::to_string(&o.session_key).unwrap()),
+		referer_tree: options.is_some_and(|o| o.referer_tree),
+		tables: table_fields(table).into_iter().map(to_list).collect(),
+		path_list: table.path_list.clone(),
+		sessions,
+		open_sessions: sessionizer.open_sessions().collect::<Vec<_>>(),
+	};
+	let mut encoder = GzEncoder::new(output, Compression::default());
+	bincode_options().serialize_into(&mut encoder, &snapshot).map_err(|e| format!("Could not write the snapshot: {}", e))?;
+	encoder.finish().map_err(|e| format!("Could not write the snapshot: {}", e))?;
+	Ok(())
+}
+
+pub fn read_snapshot(input: impl Read) -> Result<(GlobalTable, Vec<Session>, Sessionizer), String> {
+	let mut input = input;
+	let mut header = [0u8; 12];
+	match input.read_exact(&mut header) {
+		Ok(()) if &header[..8] == SNAPSHOT_MAGIC => (),
+		Ok(()) => return Err("Not a log-sankey snapshot".to_owned()),
+		Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Err("Not a log-sankey snapshot".to_owned()),
+		Err(e) => return Err(format!("Could not read the snapshot: {}", e)),
+	}
+	let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+	if version != SNAPSHOT_VERSION {
+		return Err(format!("Snapshot version {} is not supported, expected version {}", version, SNAPSHOT_VERSION));
+	}
+
+	let snapshot: Snapshot<Vec<Session>, Vec<Session>> = bincode_options().deserialize_from(GzDecoder::new(input))
+		.map_err(|e| format!("Invalid snapshot: {}", e))?;
+	for s in snapshot.sessions.iter().chain(&snapshot.open_sessions) {
+		validate_session(s, &snapshot.tables, snapshot.path_list.len()).map_err(|e| format!("Invalid snapshot: {}", e))?;
+	}
+	let options = match snapshot.session_key {
+		Some(key) => Some(SessionOptions {
+			session_key: serde_json::from_str(&key).map_err(|e| format!("Invalid snapshot: {}", e))?,
+			referer_tree: snapshot.referer_tree,
+		}),
+		None => None,
+	};
+	let tables: Vec<HashMap<String, u32>> = snapshot.tables.into_iter()
+		.map(|list| list.into_iter().enumerate().filter_map(|(id, s)| Some((s?, id as u32))).collect())
+		.collect();
+	let Ok([ip, http_version, method, domain, referer, user_agent, content_type, compression_type, remote_user, session_id, country]) = <[_; 11]>::try_from(tables) else {
+		return Err("Invalid snapshot: unexpected number of string tables".to_owned());
+	};
+	let table = GlobalTable {
+		ip, http_version, method, domain, referer, user_agent, content_type, compression_type, remote_user, session_id, country,
+		path: snapshot.path_list.iter().enumerate().map(|(id, p)| (p.clone(), id as u32)).collect(),
+		path_list: snapshot.path_list,
+	};
+	Ok((table, snapshot.sessions, Sessionizer::from_open_sessions(snapshot.open_sessions, options)))
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::DateTime;
+
+	use super::*;
+	use crate::{parser::get_or_add, session_analyzer::ActionDetails, session_key::SessionKeyConfig};
+
+	fn options() -> SessionOptions {
+		SessionOptions { session_key: SessionKeyConfig::IpPrefix { ipv4_prefix: 16, ipv6_prefix: 48 }, referer_tree: true }
+	}
+
+	fn session(table: &mut GlobalTable, start: i64, path: &str) -> Session {
+		let ip = get_or_add(&mut table.ip, "1.2.3.4");
+		let user_agent = get_or_add(&mut table.user_agent, "Mozilla/5.0");
+		let time = DateTime::from_timestamp_millis(start).unwrap();
+		Session {
+			identity: Identity::IpUserAgent { ip, user_agent },
+			ip,
+			user_agent,
+			referer: get_or_add(&mut table.referer, "https://example.com/"),
+			country: get_or_add(&mut table.country, "CZ"),
+			start_time: time, end_time: time, last_request_time: time,
+			actions: vec![table.add_path(path)],
+			access_times: vec![0],
+			details: vec![ActionDetails {
+				status_code: 200,
+				method: get_or_add(&mut table.method, "GET"),
+				domain: get_or_add(&mut table.domain, "example.com"),
+				referer: get_or_add(&mut table.referer, "-"),
+				content_type: 1,
+				bytes: 100,
+			}],
+			parents: vec![None],
+			total_requests: 1,
+			total_bytes: 100,
+		}
+	}
+
+	fn write(table: &GlobalTable, sessions: &[Session], sessionizer: &Sessionizer) -> Vec<u8> {
+		let mut data = vec![];
+		write_snapshot(&mut data, table, sessions, sessionizer).unwrap();
+		data
+	}
+
+	#[test]
+	fn restores_the_sessions_and_tables() {
+		let mut table = GlobalTable::new();
+		let sessions = vec![session(&mut table, 1_622_505_600_123, "/a/b")];
+		let open = session(&mut table, 1_622_505_700_000, "/c");
+		let sessionizer = Sessionizer::from_open_sessions(vec![open.clone()], Some(options()));
+
+		let (restored_table, restored, restored_sessionizer) = read_snapshot(&write(&table, &sessions, &sessionizer)[..]).unwrap();
+		assert_eq!(restored_table.path_list, table.path_list);
+		assert_eq!(restored_table.path, table.path);
+		assert_eq!(table_fields(&restored_table), table_fields(&table));
+		assert_eq!(restored.len(), 1);
+		assert_eq!(restored[0].start_time, sessions[0].start_time);
+		assert_eq!(restored[0].actions, sessions[0].actions);
+		assert_eq!(restored[0].details, sessions[0].details);
+		assert_eq!(restored[0].parents, sessions[0].parents);
+		assert_eq!(restored_sessionizer.open_sessions().map(|s| s.actions.clone()).collect::<Vec<_>>(), [open.actions]);
+		assert_eq!(restored_sessionizer.options(), Some(&options()));
+	}
+
+	#[test]
+	fn keeps_missing_options() {
+		let table = GlobalTable::new();
+		let (_, sessions, sessionizer) = read_snapshot(&write(&table, &[], &Sessionizer::default())[..]).unwrap();
+		assert!(sessions.is_empty());
+		assert!(sessionizer.is_empty());
+		assert_eq!(sessionizer.options(), None);
+	}
+
+	#[test]
+	fn rejects_other_files_and_versions() {
+		assert_eq!(read_snapshot(&b"LSANK"[..]).err().unwrap(), "Not a log-sankey snapshot");
+		assert_eq!(read_snapshot(&b"127.0.0.1 - - [10/Oct/2000"[..]).err().unwrap(), "Not a log-sankey snapshot");
+		let mut data = write(&GlobalTable::new(), &[], &Sessionizer::default());
+		data[8..12].copy_from_slice(&2u32.to_le_bytes());
+		assert!(read_snapshot(&data[..]).err().unwrap().starts_with("Snapshot version 2 is not supported"));
+	}
+
+	#[test]
+	fn rejects_truncated_snapshots() {
+		let mut table = GlobalTable::new();
+		let sessions = vec![session(&mut table, 0, "/a")];
+		let data = write(&table, &sessions, &Sessionizer::default());
+		assert!(read_snapshot(&data[..data.len() - 10]).err().unwrap().starts_with("Invalid snapshot"));
+	}
+
+	#[test]
+	fn rejects_unknown_ids() {
+		let mut table = GlobalTable::new();
+		let valid = session(&mut table, 0, "/a");
+		let invalid = [
+			Session { user_agent: 100, ..valid.clone() },
+			Session { identity: Identity::SessionId(7), ..valid.clone() },
+			Session { actions: vec![table.path_list.len() as u32], ..valid.clone() },
+			Session { details: vec![ActionDetails { domain: 100, ..valid.details[0] }], ..valid.clone() },
+			Session { access_times: vec![], ..valid.clone() },
+			Session { parents: vec![Some(0)], ..valid.clone() },
+		];
+		for s in invalid {
+			let error = read_snapshot(&write(&table, &[valid.clone(), s], &Sessionizer::default())[..]).err().unwrap();
+			assert!(error.starts_with("Invalid snapshot: "), "{}", error);
+		}
+		let open = Sessionizer::from_open_sessions(vec![Session { country: 100, ..valid.clone() }], None);
+		assert_eq!(read_snapshot(&write(&table, &[], &open)[..]).err().unwrap(), "Invalid snapshot: unknown country id 100");
+	}
+
+	#[test]
+	fn limits_the_size() {
+		// a corrupted length of the path list
+		let mut body = vec![];
+		bincode::DefaultOptions::new().serialize_into(&mut body, &(None::<String>, false, Vec::<Vec<Option<String>>>::new(), u64::MAX / 2)).unwrap();
+		let mut encoder = GzEncoder::new([&SNAPSHOT_MAGIC[..], &SNAPSHOT_VERSION.to_le_bytes()].concat(), Compression::default());
+		encoder.write_all(&body).unwrap();
+		let error = read_snapshot(&encoder.finish().unwrap()[..]).err().unwrap();
+		assert!(error.starts_with("Invalid snapshot"), "{}", error);
+	}
+}
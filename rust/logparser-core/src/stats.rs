@@ -0,0 +1,633 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::{Serialize, Deserialize};
+
+use crate::{session_analyzer::Session, parser::GlobalTable, navigation::flatten_tree, rewrite::{self, RewriteRule, PathRewriter}, filter::SessionFilter};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsOptions {
+    pub resolution_sec: u32,
+    pub threshold: u32,
+    pub max_paths: u32,
+    /// the time buckets are aligned to the local time in this zone (days start at the local midnight)
+    pub timezone: Tz,
+    /// applied to the paths before the graph nodes are chosen
+    pub rewrite_rules: Vec<RewriteRule>,
+    /// only the matching sessions are in the graph, see `filter` for the syntax
+    pub filter: String,
+}
+impl StatsOptions {
+    pub fn new(resolution_sec: u32, threshold: u32, max_paths: u32) -> StatsOptions {
+        StatsOptions { resolution_sec, threshold, max_paths, timezone: Tz::UTC, rewrite_rules: rewrite::default_rules(), filter: String::new() }
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageStatRow {
+    pub category: String,
+    pub count: Vec<u32>,
+    pub time: Vec<u32>
+}
+
+// #[wasm_bindgen]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageStats {
+    /// vector of (time, count)
+    pub rows: Vec<UsageStatRow>,
+    /// in `resolution_sec` units since 1970-01-01 local time of `StatsOptions::timezone`
+    pub start_time: i64,
+    pub end_time: i64,
+    pub session_starts_only: bool
+}
+impl UsageStats {
+}
+pub fn make_inverse_core<'a>(mapping: &'a HashMap<String, u32>, default: &'a str) -> Vec<&'a str> {
+    let path_len = *mapping.values().max().unwrap() as usize + 1;
+    let mut all_keys = vec![default; path_len];
+    for (p, &i) in mapping.iter() {
+        all_keys[i as usize] = p;
+    }
+
+    all_keys
+}
+pub fn make_inverse_mapping<'a>(mapping: &'a HashMap<String, u32>, default: &'a str) -> impl Fn(&u32) -> String + 'a {
+    let all_keys = make_inverse_core(mapping, default);
+    move |&i| all_keys[i as usize].to_owned()
+}
+
+/// Number of `resolution_sec` intervals since 1970-01-01 in the local time
+pub fn time_bucket(time: DateTime<Utc>, resolution_sec: u32, timezone: &Tz) -> i64 {
+	// local time as if it was UTC, so that the buckets don't move with DST
+	let time = time.with_timezone(timezone).naive_local().and_utc().timestamp();
+	// clamp to resolution
+	time / resolution_sec as i64
+}
+
+fn calc_usage_table<Key>(
+	sessions: &[Session],
+	all_actions: bool,
+    get_property: impl Fn(&Session, usize) -> Key,
+	resolution_sec: u32,
+	timezone: &Tz,
+) -> HashMap<Key, HashMap<i64, u32>>
+	where Key: Sized + Eq + std::hash::Hash + Clone {
+	let mut usage_table: HashMap<Key, HashMap<i64, u32>> = HashMap::new();
+
+	for s in sessions.iter() {
+		assert!(!s.actions.is_empty());
+
+		let actions_range = if all_actions { 0..s.actions.len() } else { 0..1 };
+
+		
+		for (key, &time) in actions_range.map(|i| get_property(s, i)).zip(s.access_times.iter()) {
+			let time = time_bucket(s.start_time + TimeDelta::seconds(time as i64), resolution_sec, timezone);
+			if !usage_table.contains_key(&key) {
+				usage_table.insert(key.clone(), HashMap::new());
+			}
+			let time_table = usage_table.get_mut(&key).unwrap();
+			time_table.insert(time, time_table.get(&time).unwrap_or(&0) + 1);
+		}	}
+
+	usage_table
+}
+
+pub fn calc_stats<Key>(
+    sessions: &[Session],
+    opt: &StatsOptions,
+    all_actions: bool,
+    get_property: impl Fn(&Session, usize) -> Key,
+    describe_key: impl Fn(&Key) -> String
+) -> UsageStats
+    where Key: Sized + Eq + std::hash::Hash + Clone {
+
+	let usage_table = calc_usage_table(sessions, all_actions, get_property, opt.resolution_sec, &opt.timezone);
+
+    // mapping path -> time -> count
+    if usage_table.is_empty() {
+        return UsageStats { rows: vec![], start_time: 0, end_time: 0, session_starts_only: !all_actions };
+    }
+
+    let min_time = usage_table.values().flat_map(|x| x.keys()).copied().min().unwrap();
+    let max_time = usage_table.values().flat_map(|x| x.keys()).copied().max().unwrap();
+    log::debug!("min_time: {}, max_time: {}, resolution: {}", min_time, max_time, opt.resolution_sec);
+
+    // path, count, ordered by count
+    let mut usage_table_sum: Vec<(Key, u32)> =
+        usage_table.iter().map(|(key, time_table)|
+            (key.clone(), time_table.values().sum::<u32>())
+        ).filter(|&(_, count)| count >= opt.threshold).collect();
+    usage_table_sum.sort_unstable_by_key(|&(_, count)| -(count as i64));
+
+    // remove elements beyond max_paths
+    usage_table_sum.truncate(opt.max_paths as usize);
+    
+
+    let rows: Vec<UsageStatRow> = usage_table_sum.iter().map(|(key, _)| {
+        let time_table = &usage_table[key];
+        let mut x: Vec<_> = time_table.iter().map(|(time, &c)| ((time - min_time) as u32, c)).collect();
+        x.sort_unstable_by_key(|&(time, _)| time);
+        let (time, count): (Vec<u32>, Vec<u32>) = x.into_iter().unzip();
+        UsageStatRow { category: describe_key(key), count, time }
+    }).collect();
+
+    UsageStats { rows, start_time: min_time, end_time: max_time, session_starts_only: !all_actions }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransitionGraphNode {
+	pub path: String,
+	pub path_id: u32,
+	pub session_count: u32,
+	/// seconds until the next page, or until the last request (assets, API calls) on the last page of a session
+	pub median_view_time: u32,
+	pub view_time_p25: u32,
+	pub view_time_p90: u32,
+	/// number of the view times shorter than each of `VIEW_TIME_BUCKETS`, the last one is the longer ones
+	pub view_time_histogram: Vec<u32>,
+	pub drop_count: u32,
+	/// sessions which started on this page
+	pub entry_count: u32,
+	/// sessions which ended on this page
+	pub exit_count: u32,
+	/// `exit_count / session_count`
+	pub exit_rate: f32,
+	/// sessions with only this page, they are not in `session_count`
+	pub bounce_count: u32,
+	/// `bounce_count / (bounce_count + entry_count)`
+	pub bounce_rate: f32,
+	pub transfer_count: HashMap<usize, u32>,
+	/// median seconds between this page and the page in the next layer, for each of `transfer_count`
+	pub transfer_median_time: HashMap<usize, u32>,
+}
+
+/// Upper bounds (seconds) of the buckets of `TransitionGraphNode::view_time_histogram`
+pub const VIEW_TIME_BUCKETS: [u32; 7] = [10, 30, 60, 2 * 60, 5 * 60, 10 * 60, 30 * 60];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransitionGraphLayer {
+	pub nodes: Vec<TransitionGraphNode>
+}
+
+/// Which pages of the sessions are in the layers of the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum GraphMode {
+	/// layer 0 is the first page matching `must_start_with`, the next layers are the following pages
+	#[default]
+	Forward,
+	/// layer 0 is the last page matching `must_start_with`, the next layers are the pages before it,
+	/// `drop_count` is the number of sessions which started on the page.
+	/// The graph ends at the page, so the UI calls `must_start_with` "Must end with" in this mode
+	Backward,
+	/// layer `before` is the first page matching `must_contain` (after the one matching `must_start_with`),
+	/// the layers before it are the pages which led to it
+	Anchored { before: usize },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransitionGraph {
+	pub layers: Vec<TransitionGraphLayer>,
+	/// the layer of the page the graph is anchored at, see `GraphMode`
+	pub anchor_layer: usize,
+}
+
+/// Removes the repeated pages (reloads), their view time is added to the first view
+fn dedupe_actions(s: &mut Session, view_times: &mut Vec<Option<u32>>) {
+	let mut keep = vec![true; s.actions.len()];
+	let mut first = 0;
+	for i in 1..s.actions.len() {
+		if s.actions[i] == s.actions[first] {
+			keep[i] = false;
+			view_times[first] = match (view_times[first], view_times[i]) {
+				(Some(a), Some(b)) => Some(a + b),
+				(a, b) => a.or(b),
+			};
+		} else {
+			first = i;
+		}
+	}
+	retain_kept(&mut s.actions, &keep);
+	retain_kept(&mut s.access_times, &keep);
+	retain_kept(&mut s.details, &keep);
+	retain_kept(view_times, &keep);
+}
+
+fn retain_kept<T>(v: &mut Vec<T>, keep: &[bool]) {
+	let mut keep = keep.iter();
+	v.retain(|_| *keep.next().unwrap());
+}
+
+fn replace_actions(s: &mut Session, replacement_table: &HashMap<u32, u32>) -> u32 {
+	let mut replacements = 0;
+	for x in &mut s.actions {
+		if let Some(&replacement) = replacement_table.get(x) {
+			*x = replacement;
+			replacements += 1;
+		}
+	}
+	replacements
+}
+
+fn get_usage_table_sum<'a>(sessions: &[Session], path_idx: &[&'a str], threshold: u32, max_paths: u32) -> Vec<(&'a str, u32)> {
+	let mut usage_table = vec![0u32; path_idx.len()];
+
+	for s in sessions {
+		for &a in &s.actions {
+			usage_table[a as usize] += 1;
+		}
+	}
+
+
+	let mut usage_table_sum: Vec<(&'a str, u32)> =
+		usage_table.iter().enumerate()
+			.filter(|&(_, &c)| c >= threshold)
+			.map(|(i, &c)| (path_idx[i], c)).collect();
+
+	usage_table_sum.sort_unstable_by_key(|&(_, count)| -(count as i64));
+	usage_table_sum.truncate(max_paths as usize);
+
+	usage_table_sum
+}
+
+pub fn reduce_sessions(mut sessions: Vec<Session>, table: &GlobalTable, threshold: u32, max_paths: u32) -> (Vec<Session>, Vec<(&str, u32)>) {
+	let path_idx = make_inverse_core(&table.path, "");
+
+	for iteration in 0..1000 {
+		let mut usage_table_sum = get_usage_table_sum(&sessions, &path_idx, threshold, max_paths);
+
+		let whitelisted_paths: HashSet<_> = usage_table_sum.iter().map(|&(path, _)| path).collect();
+		let existing_paths: HashSet<u32> = sessions.iter().flat_map(|s| s.actions.iter().copied()).collect();
+		// paths which can't be trimmed to a known parent are kept
+		let parents: HashMap<u32, u32> =
+			existing_paths.into_iter()
+				.filter(|&p| !whitelisted_paths.contains(&path_idx[p as usize]))
+				.filter_map(|p| {
+					let path = path_idx[p as usize];
+					let trimmed_path = path[0..path.rfind('/')?].trim_end_matches('/');
+					table.path.get(trimmed_path).map(|&parent| (p, parent))
+				})
+				.collect();
+		let path_length = |p: u32| path_idx[p as usize].matches("/").count();
+		// strip the longest paths first
+		let max_path_length = parents.keys().map(|&p| path_length(p)).max().unwrap_or(2);
+
+		let replacement_table: HashMap<u32, u32> =
+			parents.into_iter()
+				.filter(|&(p, _)| path_length(p) >= max_path_length && path_length(p) > 1)
+				.collect();
+
+
+		let mut replacements = 0;
+
+		for s in &mut sessions {
+			replacements += replace_actions(s, &replacement_table);
+		}
+
+		if replacements == 0 || iteration == 999 {
+			if iteration == 999 {
+				log::warn!("reached maximum iterations, still done {} replacements", replacements);
+			}
+			usage_table_sum.push(("Rest", 0));
+			return (sessions, usage_table_sum);
+		}
+	}
+
+
+	unreachable!()
+}
+
+pub fn calc_graph(
+	sessions: &[Session],
+	table: &GlobalTable,
+	graph_length: usize,
+	opt: &StatsOptions,
+	must_contain: &str,
+	must_start_with: &str,
+	mode: GraphMode,
+) -> Result<TransitionGraph, String> {
+	let (replacement_table, rewritten_paths) = PathRewriter::new(&opt.rewrite_rules)?.replacement_table(table);
+	let filter = SessionFilter::new(&opt.filter, table, opt.timezone)?;
+	let table = rewritten_paths.as_ref().unwrap_or(table);
+
+	let contains_filter: HashSet<u32> = table.path.iter().filter(|&(k, _)| k.contains(must_contain)).map(|(_, &id)| id).collect();
+	let starts_filter: HashSet<u32> = table.path.iter().filter(|&(k, _)| k.starts_with(must_start_with)).map(|(_, &id)| id).collect();
+
+	// every branch of a navigation tree is a separate sequence, `origin` is the index of the session it comes from
+	let (sessions, layout): (Vec<Session>, Vec<SessionLayout>) =
+		sessions.iter().filter(|s| filter.matches(s)).enumerate()
+		.flat_map(|(origin, s)| {
+			let branches = flatten_tree(s);
+			let single_branch = branches.len() == 1;
+			branches.into_iter().map(move |b| (origin, single_branch, b))
+		})
+		.filter_map(|(origin, single_branch, mut s)| {
+			// before the reloads are removed, the last page is viewed until the last request
+			let mut view_times = view_times(&s);
+			dedupe_actions(&mut s, &mut view_times);
+			let is_start = |a: &u32| starts_filter.contains(a);
+			let layout = match mode {
+				GraphMode::Forward | GraphMode::Anchored { .. } => {
+					let x = s.actions.iter().position(is_start)?;
+					s.actions.drain(0..x);
+					s.access_times.drain(0..x);
+					s.details.drain(0..x);
+					view_times.drain(0..x);
+					let anchor = match mode {
+						GraphMode::Anchored { .. } => s.actions.iter().position(|a| contains_filter.contains(a))?,
+						_ => 0,
+					};
+					SessionLayout { origin, single_branch, anchor, view_times, skipped: x, last_action: s.actions.len() - 1 }
+				},
+				GraphMode::Backward => {
+					let x = s.actions.iter().rposition(is_start)?;
+					let layout = SessionLayout { origin, single_branch, anchor: x, view_times, skipped: 0, last_action: s.actions.len() - 1 };
+					s.actions.truncate(x + 1);
+					s.access_times.truncate(x + 1);
+					s.details.truncate(x + 1);
+					layout
+				},
+			};
+			replace_actions(&mut s, &replacement_table);
+			Some((s, layout))
+		}).filter(|(s, _)| s.actions.iter().any(|a| contains_filter.contains(a))).unzip();
+	let (anchor_layer, step) = match mode {
+		GraphMode::Forward => (0, 1),
+		GraphMode::Backward => (0, -1),
+		GraphMode::Anchored { before } => (before, 1),
+	};
+
+	let (sessions, usage_table_sum) = reduce_sessions(sessions, table, opt.threshold, opt.max_paths);
+
+	let nodes: Vec<TransitionGraphNode> =
+		usage_table_sum.iter().map(|&(path, _count)| {
+			TransitionGraphNode {
+				path: path.to_owned(),
+				path_id: *table.path.get(path).unwrap_or(&0),
+				session_count: 0,
+				median_view_time: 0,
+				view_time_p25: 0,
+				view_time_p90: 0,
+				view_time_histogram: vec![0; VIEW_TIME_BUCKETS.len() + 1],
+				drop_count: 0,
+				entry_count: 0,
+				exit_count: 0,
+				exit_rate: 0.0,
+				bounce_count: 0,
+				bounce_rate: 0.0,
+				transfer_count: HashMap::new(),
+				transfer_median_time: HashMap::new(),
+			}
+		}).collect();
+	let rest_node_index = nodes.len() - 1;
+	let node_index: HashMap<u32, usize> = nodes.iter().enumerate().map(|(i, n)| (n.path_id, i)).collect();
+	let get_node_index = |path_id: u32| *node_index.get(&path_id).unwrap_or(&rest_node_index);
+	let mut layers = vec![ TransitionGraphLayer { nodes }; graph_length ];
+
+	for (i, layer) in layers.iter_mut().enumerate() {
+
+		let mut visit_times = vec![ vec![]; layer.nodes.len()];
+		let mut transfer_times: Vec<HashMap<usize, Vec<u32>>> = vec![ HashMap::new(); layer.nodes.len()];
+		// the branches of a navigation tree share their start, each session is counted once in a node and a transfer
+		let mut counted: HashSet<(usize, usize)> = HashSet::new();
+		let mut counted_ends: HashSet<(usize, usize)> = HashSet::new();
+		let mut counted_transfers: HashSet<(usize, usize, usize)> = HashSet::new();
+
+		for (s, l) in sessions.iter().zip(&layout) {
+			let Some(action) = (l.anchor as isize + step * (i as isize - anchor_layer as isize)).try_into().ok().filter(|&a: &usize| a < s.actions.len()) else {
+				continue;
+			};
+			let path: u32 = s.actions[action];
+			let node = &mut layer.nodes[get_node_index(path)];
+			let first_count = counted.insert((l.origin, get_node_index(path)));
+			let is_entry = l.skipped == 0 && action == 0;
+
+			if s.actions.len() == 1 && l.last_action == 0 && l.single_branch {
+				if is_entry {
+					node.bounce_count += 1;
+				}
+				continue;
+			}
+			if s.actions.len() <= 1 {
+				// single-action sessions (the bots are already excluded by `BotFilter`)
+				continue;
+			}
+
+			if first_count {
+				if let Some(visit_time) = l.view_times[action] {
+					visit_times[get_node_index(path)].push(visit_time);
+				}
+				node.session_count += 1;
+				node.entry_count += is_entry as u32;
+			}
+			let next = action.checked_add_signed(step).filter(|&a| a < s.actions.len());
+			let first_end = (action == l.last_action || next.is_none()) && counted_ends.insert((l.origin, get_node_index(path)));
+			if action == l.last_action && first_end {
+				node.exit_count += 1;
+			}
+			if let Some(next) = next {
+				let next_node = get_node_index(s.actions[next]);
+				if counted_transfers.insert((l.origin, get_node_index(path), next_node)) {
+					node.transfer_count.entry(next_node).and_modify(|x| *x += 1).or_insert(1);
+					transfer_times[get_node_index(path)].entry(next_node).or_default().push(s.access_times[next].abs_diff(s.access_times[action]));
+				}
+			} else if first_end {
+				node.drop_count += 1;
+			}
+		}
+
+		for (i, n) in layer.nodes.iter_mut().enumerate() {
+			let times = &mut visit_times[i];
+			if !times.is_empty() {
+				times.sort_unstable();
+				let percentile = |p: usize| times[(times.len() - 1) * p / 100];
+				n.view_time_p25 = percentile(25);
+				n.median_view_time = times[times.len() / 2];
+				n.view_time_p90 = percentile(90);
+				for &t in times.iter() {
+					n.view_time_histogram[VIEW_TIME_BUCKETS.iter().position(|&b| t < b).unwrap_or(VIEW_TIME_BUCKETS.len())] += 1;
+				}
+			}
+			if n.session_count > 0 {
+				n.exit_rate = n.exit_count as f32 / n.session_count as f32;
+			}
+			if n.bounce_count + n.entry_count > 0 {
+				n.bounce_rate = n.bounce_count as f32 / (n.bounce_count + n.entry_count) as f32;
+			}
+			for (&next, times) in transfer_times[i].iter_mut() {
+				times.sort_unstable();
+				n.transfer_median_time.insert(next, times[times.len() / 2]);
+			}
+		}
+	}
+
+	Ok(TransitionGraph { layers, anchor_layer })
+}
+
+/// Where the pages of a session are in the graph
+struct SessionLayout {
+	/// index of the session, the branches of one navigation tree have the same `origin`
+	origin: usize,
+	/// the session is not split into more branches, so one page means a bounce
+	single_branch: bool,
+	/// index of the page in the anchor layer
+	anchor: usize,
+	/// seconds until the next page, for each page
+	view_times: Vec<Option<u32>>,
+	/// number of pages removed from the start (before `must_start_with`)
+	skipped: usize,
+	/// index of the last page of the session, it may be removed in the backward mode
+	last_action: usize,
+}
+
+/// Seconds until the next page, the last page of the session is viewed until the last request.
+/// `None` when the next page is earlier, `get_sessions` keeps the times in order, but the sessions may come from elsewhere.
+fn view_times(s: &Session) -> Vec<Option<u32>> {
+	let last_page = (s.end_time - s.start_time).num_seconds();
+	let last_request = (s.last_request_time - s.start_time).num_seconds();
+	(0..s.access_times.len()).map(|i| match s.access_times.get(i + 1) {
+		Some(next) => next.checked_sub(s.access_times[i]),
+		None if s.access_times[i] as i64 == last_page && last_request > last_page => Some((last_request - last_page) as u32),
+		None => None,
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::DateTime;
+
+	use super::*;
+	use crate::{session_analyzer::ActionDetails, session_key::Identity};
+
+	/// Session of the `(path, second)` page views
+	fn session(table: &mut GlobalTable, pages: &[(&str, u32)]) -> Session {
+		let start_time = DateTime::from_timestamp(0, 0).unwrap();
+		let end_time = start_time + TimeDelta::seconds(pages.last().map_or(0, |&(_, t)| t) as i64);
+		let details = ActionDetails { status_code: 200, method: 0, domain: 0, referer: 0, content_type: 0, bytes: 0 };
+		Session {
+			identity: Identity::IpUserAgent { ip: 0, user_agent: 0 },
+			ip: 0, user_agent: 0, referer: 0, country: 0,
+			start_time, end_time, last_request_time: end_time,
+			actions: pages.iter().map(|&(p, _)| table.add_path(p)).collect(),
+			access_times: pages.iter().map(|&(_, t)| t).collect(),
+			details: vec![details; pages.len()],
+			parents: vec![],
+			total_requests: pages.len() as u32,
+			total_bytes: 0,
+		}
+	}
+
+	fn options(max_paths: u32, rewrite_rules: Vec<RewriteRule>) -> StatsOptions {
+		StatsOptions { rewrite_rules, ..StatsOptions::new(0, 1, max_paths) }
+	}
+
+	fn node<'a>(layer: &'a TransitionGraphLayer, path: &str) -> &'a TransitionGraphNode {
+		layer.nodes.iter().find(|n| n.path == path).unwrap()
+	}
+
+	fn node_paths(layer: &TransitionGraphLayer) -> Vec<&str> {
+		let mut paths: Vec<_> = layer.nodes.iter().map(|n| n.path.as_str()).collect();
+		paths.sort_unstable();
+		paths
+	}
+
+	#[test]
+	fn normalizes_the_rewritten_paths() {
+		let mut table = GlobalTable::new();
+		let sessions = [
+			session(&mut table, &[("/home", 0), ("/a/1/2", 10), ("/b/c", 20)]),
+			session(&mut table, &[("/home", 0), ("/a/1/3", 10), ("/b/d", 20)]),
+		];
+		let paths = table.path_list.len();
+		let rules = vec![
+			RewriteRule::Regex { pattern: "^/a/".to_owned(), replacement: "x/a/".to_owned() },
+			RewriteRule::Regex { pattern: r"^/b/(\w+)$".to_owned(), replacement: "/b//$1/page/".to_owned() },
+		];
+		let g = calc_graph(&sessions, &table, 3, &options(10, rules.clone()), "", "", GraphMode::Forward).unwrap();
+		assert_eq!(node_paths(&g.layers[0]), ["/b/c/page", "/b/d/page", "/home", "/x/a/1/2", "/x/a/1/3", "Rest"]);
+		// too many nodes, the rewritten paths are trimmed to their parents
+		let g = calc_graph(&sessions, &table, 3, &options(2, rules), "", "", GraphMode::Forward).unwrap();
+		assert!(g.layers[0].nodes.iter().all(|n| n.path == "Rest" || (n.path.starts_with('/') && !n.path.contains("//"))));
+		// the new paths are only used by the query
+		assert_eq!(table.path_list.len(), paths);
+	}
+
+	#[test]
+	fn layers_go_back_from_the_last_page() {
+		let mut table = GlobalTable::new();
+		let sessions = [
+			session(&mut table, &[("/a", 0), ("/b", 10), ("/c", 20), ("/d", 30)]),
+			session(&mut table, &[("/x", 0), ("/c", 5), ("/e", 15)]),
+			session(&mut table, &[("/c", 0), ("/b", 10), ("/c", 30), ("/y", 40)]),
+		];
+		let g = calc_graph(&sessions, &table, 3, &options(10, vec![]), "", "/c", GraphMode::Backward).unwrap();
+		assert_eq!(g.anchor_layer, 0);
+		let c = node(&g.layers[0], "/c");
+		assert_eq!(c.session_count, 3);
+		// the pages after /c are cut off
+		assert_eq!(c.exit_count, 0);
+		let layer1 = &g.layers[1];
+		let next = |path: &str| c.transfer_count[&layer1.nodes.iter().position(|n| n.path == path).unwrap()];
+		assert_eq!((next("/b"), next("/x")), (2, 1));
+		// the second session started on /x, the third one on /c two pages before the last /c
+		assert_eq!(node(layer1, "/x").drop_count, 1);
+		assert_eq!(node(&g.layers[2], "/a").session_count, 1);
+		assert_eq!(node(&g.layers[2], "/c").session_count, 1);
+		assert_eq!(node(&g.layers[2], "/c").drop_count, 1);
+	}
+
+	#[test]
+	fn anchors_at_the_contained_page() {
+		let mut table = GlobalTable::new();
+		let sessions = [
+			session(&mut table, &[("/a", 0), ("/b", 10), ("/goal", 20), ("/c", 30)]),
+			session(&mut table, &[("/goal", 0), ("/d", 10)]),
+			// never reaches the page, it's not in the graph
+			session(&mut table, &[("/a", 0), ("/b", 10), ("/e", 20)]),
+		];
+		// more layers before the anchor than the sessions have pages
+		let g = calc_graph(&sessions, &table, 5, &options(10, vec![]), "/goal", "", GraphMode::Anchored { before: 3 }).unwrap();
+		assert_eq!(g.anchor_layer, 3);
+		let counts = |layer: usize| {
+			let mut c: Vec<_> = g.layers[layer].nodes.iter().filter(|n| n.session_count > 0).map(|n| (n.path.as_str(), n.session_count)).collect();
+			c.sort_unstable();
+			c
+		};
+		assert_eq!(counts(0), []);
+		assert_eq!(counts(1), [("/a", 1)]);
+		assert_eq!(counts(2), [("/b", 1)]);
+		assert_eq!(counts(3), [("/goal", 2)]);
+		assert_eq!(counts(4), [("/c", 1), ("/d", 1)]);
+		assert_eq!(node(&g.layers[1], "/a").entry_count, 1);
+		assert_eq!(node(&g.layers[3], "/goal").entry_count, 1);
+		assert!(!node_paths(&g.layers[0]).contains(&"/e"));
+	}
+
+	#[test]
+	fn computes_the_view_time_distribution() {
+		let mut table = GlobalTable::new();
+		let mut sessions: Vec<_> = [5, 20, 45, 100, 700].iter().map(|&t| session(&mut table, &[("/a", 0), ("/b", t)])).collect();
+		sessions.push(session(&mut table, &[("/a", 0)]));
+		let g = calc_graph(&sessions, &table, 2, &options(10, vec![]), "", "", GraphMode::Forward).unwrap();
+		let a = node(&g.layers[0], "/a");
+		assert_eq!((a.view_time_p25, a.median_view_time, a.view_time_p90), (20, 45, 100));
+		// [10, 30, 60, 2 * 60, 5 * 60, 10 * 60, 30 * 60] and the longer ones
+		assert_eq!(a.view_time_histogram, [1, 1, 1, 1, 0, 0, 1, 0]);
+		assert_eq!((a.session_count, a.entry_count, a.exit_count), (5, 5, 0));
+		assert_eq!(a.bounce_count, 1);
+		assert_eq!(a.bounce_rate, 1.0 / 6.0);
+		let b = node(&g.layers[1], "/b");
+		assert_eq!((b.session_count, b.entry_count, b.exit_count, b.exit_rate), (5, 0, 5, 1.0));
+	}
+
+	#[test]
+	fn adds_the_reloads_to_the_view_time() {
+		let mut table = GlobalTable::new();
+		let mut s = session(&mut table, &[("/a", 0), ("/a", 5), ("/b", 15), ("/b", 20)]);
+		// the assets of the reloaded last page are loaded until 50
+		s.last_request_time = s.end_time + TimeDelta::seconds(30);
+		let g = calc_graph(&[s], &table, 2, &options(10, vec![]), "", "", GraphMode::Forward).unwrap();
+		assert_eq!(node(&g.layers[0], "/a").median_view_time, 15);
+		assert_eq!(node(&g.layers[1], "/b").median_view_time, 35);
+	}
+}
@@ -0,0 +1,38 @@
+use futures::{StreamExt, Stream, stream};
+
+/// Splits the byte chunks into lines, errors are passed through.
+/// The last line is emitted at the end of the input even without the trailing newline.
+pub fn bytes_to_lines<T: Stream<Item=Result<Vec<u8>, String>>>(bytes: T) -> impl Stream<Item=Result<Vec<String>, String>> {
+	let mut remainder: Vec<u8> = Vec::new();
+	// None marks the end of the input
+	bytes.map(Some).chain(stream::iter([None])).map(move |bytes| {
+		let Some(bytes) = bytes else {
+			if remainder.is_empty() {
+				return Ok(vec![]);
+			}
+			return Ok(vec![ String::from_utf8_lossy(&std::mem::take(&mut remainder)).into_owned() ]);
+		};
+		let bytes = bytes?;
+		let bytelines = bytes.split(|b| *b == b'\n').collect::<Vec<_>>();
+		if bytelines.len() == 1 {
+			remainder.extend(bytes);
+			return Ok(vec![]);
+		}
+		assert!(bytelines.len() > 1);
+
+		let lines = bytelines.iter().enumerate().take(bytelines.len() - 1).map(|(i, &line)| {
+			if i == 0 {
+				let mut remainder2 = vec![];
+				std::mem::swap(&mut remainder2, &mut remainder);
+
+				remainder2.extend(line);
+				return String::from_utf8_lossy(&remainder2).into_owned();
+			}
+			String::from_utf8_lossy(line).into_owned()
+		}).collect();
+
+		remainder.extend(bytelines[bytelines.len() - 1]);
+
+		Ok(lines)
+	})
+}
@@ -0,0 +1,180 @@
+//! Visitors: the sessions of the same person, and whether they come back in the following weeks
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Serialize, Deserialize};
+
+use crate::{session_analyzer::Session, session_key::Identity};
+
+/// How the sessions of a visitor are recognized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "key", rename_all = "snake_case")]
+pub enum VisitorKey {
+	/// the identity the requests were joined into the sessions by, see `LoadOptions::session_key`
+	#[default]
+	SessionIdentity,
+	/// IP address and user agent of the first request, when the session identity changes between the visits (e.g. session cookies)
+	IpUserAgent,
+}
+
+impl VisitorKey {
+	fn of(self, s: &Session) -> Identity {
+		match self {
+			VisitorKey::SessionIdentity => s.identity,
+			VisitorKey::IpUserAgent => Identity::IpUserAgent { ip: s.ip, user_agent: s.user_agent },
+		}
+	}
+}
+
+pub struct Visitor {
+	pub key: Identity,
+	/// indices of the sessions, ordered by the start time
+	pub sessions: Vec<usize>,
+	pub first_seen: DateTime<Utc>,
+	pub last_seen: DateTime<Utc>,
+}
+
+pub fn group_visitors(sessions: &[Session], key: VisitorKey) -> Vec<Visitor> {
+	let mut by_start: Vec<usize> = (0..sessions.len()).collect();
+	by_start.sort_by_key(|&i| sessions[i].start_time);
+
+	let mut visitors: Vec<Visitor> = vec![];
+	let mut index: HashMap<Identity, usize> = HashMap::new();
+	for i in by_start {
+		let s = &sessions[i];
+		let k = key.of(s);
+		match index.get(&k) {
+			Some(&v) => {
+				visitors[v].sessions.push(i);
+				visitors[v].last_seen = visitors[v].last_seen.max(s.last_request_time);
+			},
+			None => {
+				index.insert(k, visitors.len());
+				visitors.push(Visitor { key: k, sessions: vec![i], first_seen: s.start_time, last_seen: s.last_request_time });
+			},
+		}
+	}
+	visitors
+}
+
+/// Visitors first seen in a week
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cohort {
+	/// the Monday of the week, `2021-05-03`
+	pub week: String,
+	pub visitors: u32,
+	/// visitors which had a session N weeks after the first one, `retained[0]` is `visitors`
+	pub retained: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CohortRetention {
+	pub visitors: u32,
+	/// visitors with more than one session
+	pub returning_visitors: u32,
+	/// ordered by the week, only the weeks with new visitors
+	pub cohorts: Vec<Cohort>,
+}
+
+/// Weekly retention, the weeks start on Monday in the local time of `timezone`
+pub fn cohort_retention(sessions: &[Session], key: VisitorKey, timezone: &Tz) -> CohortRetention {
+	let week = |t: DateTime<Utc>| t.with_timezone(timezone).date_naive().week(Weekday::Mon).first_day();
+	let last_week = sessions.iter().map(|s| week(s.start_time)).max();
+	let visitors = group_visitors(sessions, key);
+
+	let mut cohorts: BTreeMap<NaiveDate, Vec<u32>> = BTreeMap::new();
+	for v in &visitors {
+		let first_week = week(v.first_seen);
+		let weeks = ((last_week.unwrap() - first_week).num_days() / 7) as usize + 1;
+		let retained = cohorts.entry(first_week).or_insert_with(|| vec![0; weeks]);
+		let mut active: Vec<usize> = v.sessions.iter().map(|&i| ((week(sessions[i].start_time) - first_week).num_days() / 7) as usize).collect();
+		active.dedup();
+		for w in active {
+			retained[w] += 1;
+		}
+	}
+
+	CohortRetention {
+		visitors: visitors.len() as u32,
+		returning_visitors: visitors.iter().filter(|v| v.sessions.len() > 1).count() as u32,
+		cohorts: cohorts.into_iter().map(|(week, retained)| Cohort {
+			week: week.format("%Y-%m-%d").to_string(),
+			visitors: retained[0],
+			retained,
+		}).collect(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Session of `ip` + user agent 1 started at the RFC 3339 `time`
+	fn session(identity: Identity, ip: u32, time: &str) -> Session {
+		let time = DateTime::parse_from_rfc3339(time).unwrap().to_utc();
+		Session {
+			identity,
+			ip, user_agent: 1, referer: 0, country: 0,
+			start_time: time, end_time: time, last_request_time: time,
+			actions: vec![0],
+			access_times: vec![0],
+			details: vec![],
+			parents: vec![],
+			total_requests: 1,
+			total_bytes: 0,
+		}
+	}
+
+	fn visitor(ip: u32) -> Identity {
+		Identity::IpUserAgent { ip, user_agent: 1 }
+	}
+
+	fn weeks(r: &CohortRetention) -> Vec<(&str, u32, Vec<u32>)> {
+		r.cohorts.iter().map(|c| (c.week.as_str(), c.visitors, c.retained.clone())).collect()
+	}
+
+	#[test]
+	fn counts_the_returning_visitors_of_each_week() {
+		let sessions = [
+			session(visitor(1), 1, "2021-05-03T10:00:00Z"),
+			session(visitor(1), 1, "2021-05-04T10:00:00Z"),
+			session(visitor(1), 1, "2021-05-19T10:00:00Z"),
+			session(visitor(2), 2, "2021-05-09T23:00:00Z"),
+			session(visitor(2), 2, "2021-05-10T01:00:00Z"),
+			session(visitor(3), 3, "2021-05-12T10:00:00Z"),
+		];
+		let r = cohort_retention(&sessions, VisitorKey::SessionIdentity, &Tz::UTC);
+		assert_eq!((r.visitors, r.returning_visitors), (3, 2));
+		// both sessions of the first week are counted once
+		assert_eq!(weeks(&r), [("2021-05-03", 2, vec![2, 1, 1]), ("2021-05-10", 1, vec![1, 0])]);
+		assert!(r.cohorts.iter().all(|c| c.retained[0] == c.visitors));
+	}
+
+	#[test]
+	fn starts_the_weeks_on_monday_in_the_timezone() {
+		// Sunday 22:30 UTC is Monday 00:30 in Prague
+		let sessions = [session(visitor(1), 1, "2021-05-02T22:30:00Z"), session(visitor(2), 2, "2021-05-02T21:30:00Z")];
+		let r = cohort_retention(&sessions, VisitorKey::SessionIdentity, &Tz::UTC);
+		assert_eq!(weeks(&r), [("2021-04-26", 2, vec![2])]);
+		let r = cohort_retention(&sessions, VisitorKey::SessionIdentity, &Tz::Europe__Prague);
+		assert_eq!(weeks(&r), [("2021-04-26", 1, vec![1, 0]), ("2021-05-03", 1, vec![1])]);
+	}
+
+	#[test]
+	fn groups_by_the_chosen_key() {
+		// the session cookie changes between the visits
+		let sessions = [
+			session(Identity::SessionId(1), 1, "2021-05-03T10:00:00Z"),
+			session(Identity::SessionId(2), 1, "2021-05-11T10:00:00Z"),
+		];
+		let r = cohort_retention(&sessions, VisitorKey::SessionIdentity, &Tz::UTC);
+		assert_eq!((r.visitors, r.returning_visitors), (2, 0));
+		let r = cohort_retention(&sessions, VisitorKey::IpUserAgent, &Tz::UTC);
+		assert_eq!((r.visitors, r.returning_visitors), (1, 1));
+		assert_eq!(weeks(&r), [("2021-05-03", 1, vec![1, 1])]);
+		let v = group_visitors(&sessions, VisitorKey::IpUserAgent);
+		assert_eq!(v[0].key, visitor(1));
+		assert_eq!(v[0].sessions, [0, 1]);
+	}
+}
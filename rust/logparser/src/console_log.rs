@@ -0,0 +1,34 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use web_sys::console;
+
+/// Sends the messages from the `log` crate to the browser console
+struct ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+        let msg = format!("{}", record.args()).into();
+        match record.level() {
+            Level::Error => console::error_1(&msg),
+            Level::Warn => console::warn_1(&msg),
+            _ => console::log_1(&msg),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+pub fn init() {
+    // fails only when a logger is already installed
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
@@ -1,154 +1,188 @@
-#![feature(map_first_last)]
-#![allow(dead_code)]
-#![allow(unused_imports)]
-mod parser;
-mod streamutil;
-mod session_analyzer;
-#[macro_use] mod util;
-mod stats;
+//! wasm bindings of `logparser-core`, the parsed sessions are kept in memory between the calls
+mod console_log;
 
 use lazy_static::lazy_static;
-use serde::{Serialize, Deserialize};
-use stats::{calc_stats, make_inverse_mapping, calc_graph};
+use serde::Serialize;
+use logparser_core::{parser, session_analyzer::{Session, Sessionizer}, stats::{self, calc_graph, GraphMode}, dimension::{self, Dimension}, funnel::{FunnelOptions, calc_funnel}, session_stats, visitors::{self, VisitorKey}, snapshot, load_sessions, flush_sessions, LoadOptions};
 
-use std::{panic, sync::Mutex, collections::{HashMap, HashSet}};
+use std::{panic, sync::Mutex};
 
-use futures::{StreamExt, stream};
+use futures::StreamExt;
 use js_sys::Uint8Array;
-use session_analyzer::Session;
-use wasm_bindgen::{prelude::*, JsValue, JsCast, JsObject, convert::{IntoWasmAbi, WasmAbi, ResultAbi}, describe::WasmDescribe};
-use wasm_bindgen_futures::JsFuture;
-use web_sys::{console, window, Response};
+use wasm_bindgen::{prelude::*, JsValue};
 use wasm_streams::ReadableStream;
 
-use crate::stats::StatsOptions;
-
 lazy_static! {
     static ref SESSIONS: Mutex<Vec<Session>> = Mutex::new(vec![]);
     static ref SYMBOL_TABLE: Mutex<parser::GlobalTable> = Mutex::new(parser::GlobalTable::new());
+    /// sessions left open by `load_logs` with `keep_open_sessions`, they aren't in SESSIONS until they are flushed
+    static ref SESSIONIZER: Mutex<Sessionizer> = Mutex::new(Sessionizer::default());
+}
+
+fn to_js<T: Serialize>(value: &T) -> JsValue {
+    // json_compatible: HashMaps are serialized as plain objects, not as JS Map
+    value.serialize(&serde_wasm_bindgen::Serializer::json_compatible()).unwrap()
+}
+
+#[wasm_bindgen(start)]
+pub fn start() {
+    panic::set_hook(Box::new(console_error_panic_hook::hook));
+    console_log::init();
+}
+
+#[wasm_bindgen]
+pub struct StatsOptions(stats::StatsOptions);
+#[wasm_bindgen]
+impl StatsOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new(resolution_sec: u32, threshold: u32, max_paths: u32) -> StatsOptions {
+        StatsOptions(stats::StatsOptions::new(resolution_sec, threshold, max_paths))
+    }
+
+    /// IANA name of the zone used for the time buckets, UTC by default
+    pub fn set_timezone(&mut self, timezone: &str) -> Result<(), JsError> {
+        self.0.timezone = parser::parse_timezone(timezone).map_err(|e| JsError::new(&e))?;
+        Ok(())
+    }
+
+    /// filter expression of the sessions in the transition graph, e.g. `contains /h/** and not ua *bot*`
+    pub fn set_filter(&mut self, filter: &str) {
+        self.0.filter = filter.to_owned();
+    }
+
+    /// array of RewriteRule objects (see wasm-facade.ts) replacing the default rules
+    pub fn set_rewrite_rules(&mut self, rules: JsValue) -> Result<(), JsError> {
+        self.0.rewrite_rules = serde_wasm_bindgen::from_value(rules)?;
+        Ok(())
+    }
 }
 
 #[wasm_bindgen]
 pub fn clear_sessions() {
     SESSIONS.lock().unwrap().clear();
+    *SESSIONIZER.lock().unwrap() = Sessionizer::default();
     let mut t = SYMBOL_TABLE.lock().unwrap();
     *t = parser::GlobalTable::new();
 }
 
+/// closes the sessions left open by `load_logs` with `keep_open_sessions`, returns their number (without the bots)
 #[wasm_bindgen]
-pub fn usage_stats_by_path(opt: StatsOptions) -> JsValue {
+pub fn flush_open_sessions(options: JsValue) -> Result<usize, JsError> {
+    // the LoadOptions object, only the bot filter is used
+    let options: LoadOptions = serde_wasm_bindgen::from_value(options)?;
+    let symbols = SYMBOL_TABLE.lock().unwrap();
+
+    let mut sessions = flush_sessions(&mut SESSIONIZER.lock().unwrap(), &options, &symbols).map_err(|e| JsError::new(&e))?;
+    let count = sessions.len();
+    SESSIONS.lock().unwrap().append(&mut sessions);
+    Ok(count)
+}
+
+/// binary snapshot of the loaded sessions (including the open ones), it can be stored (e.g. in IndexedDB) and opened with `load_snapshot`
+#[wasm_bindgen]
+pub fn save_snapshot() -> Result<Vec<u8>, JsError> {
     let sessions = SESSIONS.lock().unwrap();
     let symbols = SYMBOL_TABLE.lock().unwrap();
 
-    let r = calc_stats(
-        &sessions,
-        &opt,
-        true,
-        |s, i| s.actions[i],
-        make_inverse_mapping(&symbols.path, &"".to_owned())
-    );
+    let mut data = vec![];
+    snapshot::write_snapshot(&mut data, &symbols, &sessions, &SESSIONIZER.lock().unwrap()).map_err(|e| JsError::new(&e))?;
+    Ok(data)
+}
 
-    JsValue::from_serde(&r).unwrap()
+/// replaces the loaded sessions with the snapshot from `save_snapshot` or `log-sankey snapshot`, the logs loaded next must use its session_key and referer_tree
+#[wasm_bindgen]
+pub fn load_snapshot(data: &[u8]) -> Result<(), JsError> {
+    let (table, sessions, sessionizer) = snapshot::read_snapshot(data).map_err(|e| JsError::new(&e))?;
+    *SESSIONS.lock().unwrap() = sessions;
+    *SESSIONIZER.lock().unwrap() = sessionizer;
+    *SYMBOL_TABLE.lock().unwrap() = table;
+    Ok(())
 }
 
+/// `dimension` is one of the Dimension strings (see wasm-facade.ts), `all_requests` counts every page view instead of the session starts
 #[wasm_bindgen]
-pub fn usage_stats_by_ua(opt: StatsOptions) -> JsValue {
+pub fn usage_stats(dimension: JsValue, opt: StatsOptions, all_requests: bool) -> Result<JsValue, JsError> {
+    let dimension: Dimension = serde_wasm_bindgen::from_value(dimension)?;
     let sessions = SESSIONS.lock().unwrap();
     let symbols = SYMBOL_TABLE.lock().unwrap();
 
-    let r = calc_stats(
-        &sessions,
-        &opt,
-        false,
-        |s, _i| s.user_agent,
-        make_inverse_mapping(&symbols.user_agent, &"".to_owned())
-    );
+    let r = dimension::usage_stats(&sessions, &symbols, dimension, &opt.0, all_requests);
 
-    JsValue::from_serde(&r).unwrap()
+    Ok(to_js(&r))
 }
 
+/// `mode` is a GraphMode object, see wasm-facade.ts. In the backward mode, `must_startwith` is the prefix of the last page
 #[wasm_bindgen]
-pub fn usage_stats_by_referer(opt: StatsOptions) -> JsValue {
+pub fn usage_transfer_graph(opt: StatsOptions, graph_length: usize, must_contain: &str, must_startwith: &str, mode: JsValue) -> Result<JsValue, JsError> {
+    let mode: GraphMode = serde_wasm_bindgen::from_value(mode)?;
     let sessions = SESSIONS.lock().unwrap();
     let symbols = SYMBOL_TABLE.lock().unwrap();
 
-    let r = calc_stats(
-        &sessions,
-        &opt,
-        false,
-        |s, _i| s.user_agent,
-        make_inverse_mapping(&symbols.referer, &"".to_owned())
-    );
+    let g = calc_graph(&sessions, &symbols, graph_length, &opt.0, must_contain, must_startwith, mode).map_err(|e| JsError::new(&e))?;
+
+    Ok(to_js(&g))
+}
+
+#[wasm_bindgen]
+pub fn session_summary(opt: StatsOptions) -> JsValue {
+    let sessions = SESSIONS.lock().unwrap();
+
+    let r = session_stats::session_summary(&sessions, &opt.0);
+
+    to_js(&r)
+}
+
+/// weekly retention of the visitors, `visitor_key` is a VisitorKey object (see wasm-facade.ts)
+#[wasm_bindgen]
+pub fn cohort_retention(opt: StatsOptions, visitor_key: JsValue) -> Result<JsValue, JsError> {
+    let visitor_key: VisitorKey = serde_wasm_bindgen::from_value(visitor_key)?;
+    let sessions = SESSIONS.lock().unwrap();
+
+    let r = visitors::cohort_retention(&sessions, visitor_key, &opt.0.timezone);
 
-    JsValue::from_serde(&r).unwrap()
+    Ok(to_js(&r))
 }
 
+/// `options` is a FunnelOptions object, see wasm-facade.ts
 #[wasm_bindgen]
-pub fn usage_transfer_graph(opt: StatsOptions, graph_length: usize, must_contain: &str, must_startwith: &str) -> JsValue {
+pub fn funnel(options: JsValue) -> Result<JsValue, JsError> {
+    let options: FunnelOptions = serde_wasm_bindgen::from_value(options)?;
     let sessions = SESSIONS.lock().unwrap();
     let symbols = SYMBOL_TABLE.lock().unwrap();
 
-    let g = calc_graph(&sessions, &symbols, graph_length, &opt, must_contain, must_startwith);
+    let f = calc_funnel(&sessions, &symbols, &options).map_err(|e| JsError::new(&e))?;
 
-    JsValue::from_serde(&g).unwrap()
+    Ok(to_js(&f))
 }
 
+// wasm is single-threaded, holding the lock only keeps the other calls out until the logs are loaded
+#[allow(clippy::await_holding_lock)]
 #[wasm_bindgen]
 pub async fn load_logs(
     input: Vec<wasm_streams::readable::sys::ReadableStream>,
-    pattern: String,
-    date_pattern: String,
-    capture_idxs: Vec<usize>,
-    ignore_query_string: bool,
-    max_age: u32,
+    options: JsValue,
     report_progress: js_sys::Function
-) -> u32 {
-    panic::set_hook(Box::new(console_error_panic_hook::hook));
-
-
-    let parser = parser::create_parser(&pattern, capture_idxs, &date_pattern, ignore_query_string);
-    let str: Vec<_> = input.into_iter().map(|i| ReadableStream::from_raw(i)).collect();
+) -> Result<JsValue, JsError> {
+    // the LoadOptions object, see wasm-facade.ts
+    let options: LoadOptions = serde_wasm_bindgen::from_value(options)?;
+    let str: Vec<_> = input.into_iter().map(ReadableStream::from_raw).collect();
 
     let input_streams = str.into_iter().map(|x| {
-        let byte_stream = x.into_stream().map(|jsvalue| Uint8Array::from(jsvalue.unwrap()).to_vec());
-        let byte_stream = byte_stream.map(|x| {
-            _ = report_progress.call1(&JsValue::null(), &JsValue::from_f64(x.len() as f64));
+        let byte_stream = x.into_stream().map(|jsvalue| jsvalue
+            .map(|chunk| Uint8Array::from(chunk).to_vec())
+            .map_err(|e| format!("Could not read the input: {:?}", e)));
+        byte_stream.map(|x| {
+            if let Ok(x) = &x {
+                _ = report_progress.call1(&JsValue::null(), &JsValue::from_f64(x.len() as f64));
+            }
             x
-        });
-        streamutil::bytes_to_lines(byte_stream)
-    });
-
-    let line_stream = stream::iter(input_streams).flatten();
+        })
+    }).collect();
 
     let mut symbol_table = SYMBOL_TABLE.lock().unwrap();
+    let mut sessionizer = SESSIONIZER.lock().unwrap();
+    let mut result = load_sessions(input_streams, &options, &mut symbol_table, &mut sessionizer).await.map_err(|e| JsError::new(&e))?;
+    SESSIONS.lock().unwrap().append(&mut result.sessions);
 
-    let loglines: Vec<_> = line_stream.map(|lines| lines.iter().filter_map(
-        |line| {
-
-            if line.contains("Bot/") || line.contains("bot/") {
-                // optimization: skip lines that are just fucking bots
-                return None
-            }
-            
-            match parser::parse_line_handwritten1(&parser, &mut symbol_table, line) {
-                Ok(l) => Some(l),
-                Err(e) => {
-                    // console::error_1(&JsValue::from_str(&e.to_string()));
-                    log!("Could not parse {}: {}", line, e);
-                    None
-                }
-        }}).collect::<Vec<_>>()).collect::<Vec<_>>().await;
-    let mut sessions: Vec<Session> = session_analyzer::get_sessions(&symbol_table, stream::iter(loglines), max_age).collect().await;
-    log!("Sessions (unfiltered): {}", sessions.len());
-    let bots = symbol_table.get_bots();
-    log!("Bots: {:?}", bots.iter().map(|&(_, b)| b).collect::<Vec<&str>>());
-    let bots: HashSet<u32> = bots.iter().map(|&(c, _)| c).collect();
-    sessions.retain(|s| !bots.contains(&s.user_agent));
-    log!("Sessions (filtered): {}", sessions.len());
-    log!("Session actions: {}", sessions.iter().map(|s| s.actions.len()).sum::<usize>());
-    SESSIONS.lock().unwrap().append(&mut sessions);
-
-    0
-    // loglines.count().await as u32
+    Ok(to_js(&result.summary))
 }
-
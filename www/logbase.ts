@@ -1,34 +1,41 @@
 import wasm from './wasm-facade'
 
-const parserSettings = {
-	pattern: [
-		"(\\d+-\\d+-\\d+ \\d+:\\d+:\\d+)", // 2021-05-01 02:16:03
-		'"([^"]*)"', // "81.90.168.55"
-		'"([^"]*)"', //"HTTP/1.0"
-		'(\\w+)', // GET
-		'([\\w\\-.]+)', //ksp.mff.cuni.cz
-		'"([^"]*)"', // "/sksp/2021J/pics_system/inline_menu_background_select.png"
-		'(\\d+)', // 304
-		'(\\d+)', // 0
-		'(\\d+)', // 0
-		'"([^"]*)"',// "https://ksp.mff.cuni.cz/sksp/2021J/jksp2021.css"
-		'"([^"]*)"',// "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/90.0.4430.93 Safari/537.36"
-		'"([^"]*)"',// "-"
-		'(\\d+)', // 11128
-		'"([^"]*)"',// "-"
-		'"([^"]*)"',// "-"
-
-	].join('\\s+'),
-	captures: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
-	datePattern: "%Y-%m-%d %H:%M:%S",
-	ignoreQueryString: true,
-	maxAge: 60*60
-}
-
-
-export async function loadFiles(files: File[], reportProgress: (progress: number, total: number) => void) {
+const parserSettings: LoadOptions = {
+	// name of a built-in format ("ksp", "common", "combined", "caddy", "traefik"), "auto" to detect it,
+	// or a LogFormat with named capture groups:
+	// {
+	// 	pattern: '(?P<time>\\d+-\\d+-\\d+ \\d+:\\d+:\\d+)\\s+"(?P<ip>[^"]*)"\\s+...',
+	// 	datetime_format: "%Y-%m-%d %H:%M:%S",
+	// 	fields: { domain: 5 },     // other capture groups by index or name
+	// 	optional: ["referer"],     // groups which may be missing in the match
+	// }
+	format: "ksp",
+	ignore_query_string: true,
+	max_age: 60*60,
+	max_errors: null,
+	// the logs without UTC offset (like ksp) are in local time, the same zone the stats are bucketed in by loadFiles
+	timezone: Intl.DateTimeFormat().resolvedOptions().timeZone,
+	reorder_window: 60,
+}
+
+
+// SNAPSHOT_MAGIC in snapshot.rs
+const snapshotMagic = "LSANKEY\0"
+
+async function isSnapshot(f: File) {
+	const header = new Uint8Array(await f.slice(0, snapshotMagic.length).arrayBuffer())
+	return String.fromCharCode(...header) === snapshotMagic
+}
+
+/** with `keepOpenSessions`, the sessions at the end of the files continue in the next loaded files, until `flushSessions` */
+export async function loadFiles(files: File[], reportProgress: (progress: number, total: number) => void, keepOpenSessions = false) {
 	console.time("wasm")
-	const s = parserSettings
+	// a snapshot (from `log-sankey snapshot`) replaces the loaded sessions, instead of parsing the logs
+	if (files.length === 1 && await isSnapshot(files[0])) {
+		wasm.load_snapshot(new Uint8Array(await files[0].arrayBuffer()))
+		console.timeEnd("wasm")
+		return
+	}
 	const streams: ReadableStream<Uint8Array>[] = await Promise.all(files.map(f => f.stream()))
 	
 	const totalSize = files.map(f => f.size).reduce((a, b) => a + b, 0)
@@ -37,27 +44,72 @@ export async function loadFiles(files: File[], reportProgress: (progress: number
 		currentProgress += bytes
 		reportProgress(currentProgress, totalSize)
 	}
-	const wasmResult = await wasm.load_logs(streams, s.pattern, s.datePattern, new Uint32Array(s.captures), s.ignoreQueryString, s.maxAge, reportCallback)
-	console.timeLog("wasm", "loaded files")
+	const wasmResult: LoadSummary = await wasm.load_logs(streams, { ...parserSettings, keep_open_sessions: keepOpenSessions }, reportCallback)
+	console.timeLog("wasm", "loaded files", wasmResult)
 
 	console.timeEnd("wasm")
 
 	console.time("wasm-compute")
 	const opts = new wasm.StatsOptions(60*60, 0, 300)
-	let analysis = wasm.usage_stats_by_path(opts)
+	opts.set_timezone(Intl.DateTimeFormat().resolvedOptions().timeZone)
+	let analysis = wasm.usage_stats("path", opts, true)
 	console.timeEnd("wasm-compute")
 	console.log(analysis)
 }
 
+/** closes the sessions left open by `loadFiles`, returns their number */
+export function flushSessions(): number {
+	return wasm.flush_open_sessions(parserSettings)
+}
+
 export function get_graph(
 	length = 8,
 	maxNodes = 30,
 	threshold = 3,
 	mustContain = "",
-	mustStartWith = ""
+	mustStartWith = "",
+	mode: GraphMode = { mode: "forward" },
+	filter = ""
 ): TransitionGraph {
 	const opts = new wasm.StatsOptions(0, threshold, maxNodes)
-	return wasm.usage_transfer_graph(opts, length, mustContain, mustStartWith)
+	// e.g. "contains /h/** and not (ua *bot* or referer *google*)", see filter.rs
+	opts.set_filter(filter)
+	return wasm.usage_transfer_graph(opts, length, mustContain, mustStartWith, mode)
+}
+
+
+
+function openSnapshotDb(): Promise<IDBDatabase> {
+	return new Promise((resolve, reject) => {
+		const request = indexedDB.open("log-sankey", 1)
+		request.onupgradeneeded = () => request.result.createObjectStore("snapshots")
+		request.onsuccess = () => resolve(request.result)
+		request.onerror = () => reject(request.error)
+	})
 }
 
+/** stores the loaded sessions in IndexedDB, `restoreSnapshot` opens them after a reload of the page */
+export async function saveSnapshot() {
+	const data: Uint8Array = wasm.save_snapshot()
+	const db = await openSnapshotDb()
+	await new Promise((resolve, reject) => {
+		const request = db.transaction("snapshots", "readwrite").objectStore("snapshots").put(data, "last")
+		request.onsuccess = resolve
+		request.onerror = () => reject(request.error)
+	})
+}
 
+/** returns false when no snapshot was saved */
+export async function restoreSnapshot(): Promise<boolean> {
+	const db = await openSnapshotDb()
+	const data: Uint8Array | undefined = await new Promise((resolve, reject) => {
+		const request = db.transaction("snapshots").objectStore("snapshots").get("last")
+		request.onsuccess = () => resolve(request.result)
+		request.onerror = () => reject(request.error)
+	})
+	if (!data) {
+		return false
+	}
+	wasm.load_snapshot(data)
+	return true
+}
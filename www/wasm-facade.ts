@@ -7,6 +7,179 @@ export default parserStuff
 
 declare global {
 
+	type LogFormat = {
+		pattern: string,
+		datetime_format: string,
+		fields?: { [field in LogField]?: number | string },
+		optional?: LogField[]
+	}
+
+	/** field names which can be used in LogFormat, session_id and remote_user are used by the session_key option */
+	type LogField = "time" | "ip" | "http_version" | "method" | "domain" | "path" | "status_code" | "size" | "referer" | "user_agent" | "content_type" | "compression_type" | "remote_user" | "session_id" | "country"
+
+	type LoadOptions = {
+		/** name of a built-in format or "auto" to detect it, or a LogFormat */
+		format: string | LogFormat,
+		ignore_query_string: boolean,
+		/** session is closed after this number of seconds of inactivity */
+		max_age: number,
+		/** loading fails when more lines can not be parsed, null for no limit */
+		max_errors?: number | null,
+		/** IANA zone of the logs without UTC offset (e.g. "Europe/Prague"), null for UTC */
+		timezone?: string | null,
+		/** seconds, lines of a file which are out of order by less are sorted; the files are merged by time */
+		reorder_window?: number,
+		/** the first matching rule excludes the session; by default known_crawlers and too_many_pages over 40, add no_assets to exclude the scrapers */
+		bot_filter?: { rules: BotRule[] },
+		/** how the requests are joined into sessions, ip + user agent by default */
+		session_key?:
+			| { key: "ip_user_agent" }
+			| { key: "session_id" }
+			| { key: "remote_user" }
+			| { key: "ip_prefix", ipv4_prefix?: number, ipv6_prefix?: number },
+		/** attach each page to the page in its referer, the graph is built from the branches of the navigation tree */
+		referer_tree?: boolean,
+		/** which requests are page views, the first matching rule decides */
+		classifier?: { rules: ClassRule[], default?: RequestClass },
+		/** the sessions active at the end of the logs stay open and continue in the next load_logs, until flush_open_sessions */
+		keep_open_sessions?: boolean,
+	}
+
+	type RequestClass = "page" | "asset" | "probably_asset" | "ignored"
+
+	/** all the specified conditions must match */
+	type ClassRule = {
+		/** without the dot */
+		extensions?: string[],
+		/** "text/css", or "image/*" for all the subtypes */
+		content_types?: string[],
+		/** glob patterns, e.g. "/api/**" */
+		paths?: string[],
+		methods?: string[],
+		/** "404" or "4xx" */
+		statuses?: string[],
+		class: RequestClass,
+	}
+
+	type BotRule =
+		| { rule: "known_crawlers" }
+		| { rule: "substring", pattern: string }
+		| { rule: "regex", pattern: string }
+		| { rule: "ip_range", range: string }
+		| { rule: "no_assets", min_pages: number }
+		| { rule: "too_many_pages", max_pages: number }
+
+	/** applied in order to the paths of the graph nodes, see StatsOptions.set_rewrite_rules */
+	type RewriteRule =
+		/** "$1" in the replacement is the first capture group */
+		| { rule: "regex", pattern: string, replacement: string }
+		/** the prefix and everything under it becomes the target (the prefix itself by default) */
+		| { rule: "prefix", prefix: string, target?: string }
+		/** numeric and UUID segments become the placeholder, ":id" by default */
+		| { rule: "ids", placeholder?: string }
+
+	type LoadSummary = {
+		format: string,
+		/** share of the sample lines parsed by the detected format, null when it was not detected */
+		detection_confidence: number | null
+		lines: {
+			total_lines: number
+			parsed_lines: number
+			/** empty lines */
+			skipped_lines: number
+			error_count: number
+			errors_by_kind: { [kind: string]: number }
+			/** first few lines which could not be parsed */
+			samples: { line: string, error: { kind: string, field: string | null, offset: number | null, reason: string } }[]
+		}
+		/** lines which came later than the reorder window allows */
+		out_of_order_lines: number
+		/** sessions excluded by each bot rule */
+		bots: { rule: string, sessions: number, requests: number }[]
+		/** sessions left open for the next load, see LoadOptions.keep_open_sessions */
+		open_sessions: number
+	}
+
+	/** what usage_stats groups by, user agent and country are of the first request of the session,
+	 * status_code only has the 2xx statuses unless the classifier keeps the other responses as pages */
+	type Dimension = "path" | "referer" | "referer_domain" | "user_agent" | "ua_family" | "domain" | "status_code" | "method" | "content_type" | "country"
+
+	type FunnelOptions = {
+		/** glob pattern of the path of each step, e.g. "/signup/**" */
+		steps: string[],
+		/** the steps must directly follow each other, otherwise other pages may be visited between them */
+		strict?: boolean,
+		/** maximal number of seconds between two steps */
+		max_step_time?: number | null,
+	}
+
+	type Funnel = {
+		steps: {
+			pattern: string,
+			/** sessions which reached this step */
+			sessions: number,
+			/** sessions which reached this step, but not the next one */
+			drop_off: number,
+			/** median seconds to the next step */
+			median_time_to_next: number | null,
+		}[]
+	}
+
+	type Distribution = {
+		/** upper bounds (exclusive) of the histogram buckets, the last bucket has the larger values */
+		buckets: number[],
+		histogram: number[],
+		mean: number,
+		p25: number,
+		p50: number,
+		p75: number,
+		p90: number,
+		p99: number,
+	}
+
+	type SessionSummary = {
+		sessions: number,
+		/** single-page sessions */
+		bounces: number,
+		/** seconds from the first to the last request */
+		duration: Distribution,
+		pages: Distribution,
+		bytes: Distribution,
+		/** sessions started in each time bucket which has any */
+		rows: {
+			/** in resolution units since start_time */
+			time: number,
+			sessions: number,
+			bounces: number,
+			new_visitors: number,
+			returning_visitors: number,
+			median_duration: number,
+			median_pages: number,
+		}[],
+		/** in resolution units since 1970-01-01 in the local time of the StatsOptions timezone */
+		start_time: number,
+		end_time: number,
+	}
+
+	type VisitorKey =
+		/** the identity the sessions were joined by, see LoadOptions.session_key */
+		| { key: "session_identity" }
+		/** IP address and user agent of the first request of the session */
+		| { key: "ip_user_agent" }
+
+	type CohortRetention = {
+		visitors: number,
+		/** visitors with more than one session */
+		returning_visitors: number,
+		cohorts: {
+			/** the Monday of the week the visitors were first seen, "2021-05-03" */
+			week: string,
+			visitors: number,
+			/** visitors which came N weeks after the first one, retained[0] is visitors */
+			retained: number[],
+		}[],
+	}
+
 	type UsageStatRow = {
 		category: string,
 		count: number[],
@@ -15,6 +188,7 @@ declare global {
 	
 	type UsageStats = {
 		rows: UsageStatRow[],
+		/** in resolution units since 1970-01-01 in the local time of the StatsOptions timezone */
 		start_time: number,
 		end_time: number,
 		session_starts_only: boolean
@@ -24,17 +198,43 @@ declare global {
 		path: string,
 		path_id: number,
 		session_count: number,
+		/** seconds until the next page, or until the last request on the last page of a session */
 		median_view_time: number,
+		view_time_p25: number,
+		view_time_p90: number,
+		/** counts of the view times below 10s, 30s, 1m, 2m, 5m, 10m, 30m and the longer ones */
+		view_time_histogram: number[],
 		drop_count: number,
+		/** sessions which started on this page */
+		entry_count: number,
+		/** sessions which ended on this page */
+		exit_count: number,
+		exit_rate: number,
+		/** single-page sessions, they are not in session_count */
+		bounce_count: number,
+		/** bounce_count / (bounce_count + entry_count) */
+		bounce_rate: number,
 		transfer_count: { [key: number]: number } //HashMap<u32, u32>,
+		/** median seconds to the page in the next layer */
+		transfer_median_time: { [key: number]: number },
 	}
 	
 	type TransitionGraphLayer = {
 		nodes: TransitionGraphNode[]
 	}
 	
+	type GraphMode =
+		/** layer 0 is the first page matching mustStartWith */
+		| { mode: "forward" }
+		/** layer 0 is the last page matching mustStartWith (labelled "Must end with" in this mode), the next layers are the pages before it */
+		| { mode: "backward" }
+		/** layer `before` is the first page matching mustContain, the layers before it are the previous pages */
+		| { mode: "anchored", before: number }
+
 	type TransitionGraph = {
 		layers: TransitionGraphLayer[]
+		/** the layer of the page the graph is anchored at */
+		anchor_layer: number
 	}
 }
 